@@ -0,0 +1,2 @@
+-- Row version used for optimistic concurrency control on user edits.
+ALTER TABLE users ADD COLUMN version INT NOT NULL DEFAULT 1;
@@ -0,0 +1,3 @@
+-- Soft deletion: rows with deleted_at set are hidden and purged after the retention period.
+ALTER TABLE users ADD COLUMN deleted_at DATETIME NULL;
+CREATE INDEX idx_users_deleted_at ON users (deleted_at);
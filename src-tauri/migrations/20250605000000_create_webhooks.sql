@@ -0,0 +1,35 @@
+-- Admin-managed subscriptions to user lifecycle events; `events` is a comma-separated list.
+CREATE TABLE IF NOT EXISTS webhooks (
+  id INT AUTO_INCREMENT PRIMARY KEY,
+  url VARCHAR(2048) NOT NULL,
+  secret CHAR(64) NOT NULL,
+  events VARCHAR(255) NOT NULL,
+  active BOOLEAN NOT NULL DEFAULT TRUE,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Outbox: one row per event and subscription, retried until delivered or dead.
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+  id BIGINT AUTO_INCREMENT PRIMARY KEY,
+  webhook_id INT NOT NULL,
+  event VARCHAR(32) NOT NULL,
+  payload TEXT NOT NULL,
+  status VARCHAR(16) NOT NULL DEFAULT 'pending',
+  attempts INT NOT NULL DEFAULT 0,
+  next_attempt_at DATETIME NOT NULL,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  delivered_at DATETIME NULL,
+  FOREIGN KEY (webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE,
+  INDEX idx_webhook_deliveries_due (status, next_attempt_at)
+);
+
+-- Delivery log: every HTTP attempt and its outcome.
+CREATE TABLE IF NOT EXISTS webhook_delivery_attempts (
+  id BIGINT AUTO_INCREMENT PRIMARY KEY,
+  delivery_id BIGINT NOT NULL,
+  attempted_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  status_code INT NULL,
+  error VARCHAR(1024) NULL,
+  duration_ms INT NOT NULL,
+  FOREIGN KEY (delivery_id) REFERENCES webhook_deliveries(id) ON DELETE CASCADE
+);
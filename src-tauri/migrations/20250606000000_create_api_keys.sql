@@ -0,0 +1,15 @@
+-- Admin-issued keys for scripts. Only a SHA-256 hash of the key is stored; `prefix`
+-- is the public part used to find it. `scopes` is a comma-separated list.
+CREATE TABLE IF NOT EXISTS api_keys (
+  id INT AUTO_INCREMENT PRIMARY KEY,
+  name VARCHAR(100) NOT NULL,
+  prefix CHAR(12) NOT NULL UNIQUE,
+  key_hash CHAR(64) NOT NULL,
+  role VARCHAR(20) NOT NULL,
+  scopes VARCHAR(255) NOT NULL,
+  created_by VARCHAR(255) NOT NULL,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  expires_at DATETIME NULL,
+  last_used_at DATETIME NULL,
+  revoked_at DATETIME NULL
+);
@@ -0,0 +1,2 @@
+-- Identity-provider account (`sub` claim) a user signs in with; set on the first single sign-on.
+ALTER TABLE users ADD COLUMN oidc_subject VARCHAR(255) NULL UNIQUE;
@@ -0,0 +1,2 @@
+-- Directory entry a user logs in with; set on the first LDAP login. Linked users can no longer log in with a local password.
+ALTER TABLE users ADD COLUMN ldap_dn VARCHAR(512) NULL UNIQUE;
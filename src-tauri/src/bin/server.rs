@@ -0,0 +1,11 @@
+use clap::Parser;
+use my_tauri_app_lib::cli::{self, Cli, Command};
+use my_tauri_app_lib::utils::telemetry::init_tracing;
+
+/// Standalone REST API service: runs `serve` unless another subcommand is given.
+#[tokio::main]
+async fn main() {
+    init_tracing();
+    let command = Cli::parse().command.unwrap_or(Command::Serve);
+    std::process::exit(cli::run(command).await);
+}
@@ -0,0 +1,338 @@
+use std::env;
+use std::io::{self, BufRead};
+
+use clap::{Parser, Subcommand};
+use utoipa::OpenApi;
+
+use crate::utils::auth::{create_jwt, hash_password, validate_jwt};
+use crate::utils::config::LdapConfig;
+use crate::utils::ldap::{self, LdapLogin};
+use crate::utils::models::NewUser;
+#[cfg(feature = "dev-tools")]
+use crate::utils::oidc_mock::{self, MockUser};
+use crate::utils::openapi::ApiDoc;
+#[cfg(feature = "otel")]
+use crate::utils::otel;
+use crate::utils::permissions::ROLES;
+use crate::utils::server::{connect_db, serve};
+use crate::utils::user_repo;
+use crate::utils::webhooks;
+
+/// Desktop app by default; the subcommands below run headless for ops work.
+#[derive(Debug, Parser)]
+#[command(name = "my-tauri-app", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run only the REST API, without opening a window
+    Serve,
+    /// Apply pending database migrations and exit
+    Migrate,
+    /// Manage user accounts
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Issue or inspect JWTs
+    #[command(subcommand)]
+    Token(TokenCommand),
+    /// Check the runtime configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Recovery: make the given user an admin
+    PromoteAdmin { email: String },
+    /// Print the OpenAPI document of the REST API, e.g. for client generators
+    Openapi,
+    /// Run a local HTTP receiver that prints webhook deliveries
+    WebhookStub {
+        #[arg(long, default_value = "127.0.0.1:9000")]
+        addr: String,
+        /// Signing secret of the webhook; signatures are checked when given
+        #[arg(long)]
+        secret: Option<String>,
+        /// Status code to answer with, e.g. 500 to exercise retries
+        #[arg(long, default_value_t = 200)]
+        status: u16,
+    },
+    /// Check a login against the LDAP directory without touching the database;
+    /// the password is read from stdin unless --password is given
+    LdapTest {
+        login: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Run a local OpenID provider that approves every sign-in, for trying single sign-on
+    #[cfg(feature = "dev-tools")]
+    OidcMock {
+        #[arg(long, default_value = "127.0.0.1:9100")]
+        addr: String,
+        #[arg(long, default_value = "admin@example.com")]
+        email: String,
+        #[arg(long, default_value = "Mock Admin")]
+        name: String,
+        /// Groups put in the ID token, comma-separated
+        #[arg(long, value_delimiter = ',', default_value = "admins")]
+        groups: Vec<String>,
+    },
+    /// Run a local OTLP/HTTP receiver that prints exported spans
+    #[cfg(feature = "otel")]
+    OtelStub {
+        #[arg(long, default_value = "127.0.0.1:4318")]
+        addr: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum UserCommand {
+    /// Create a user; the password is read from stdin unless --password is given
+    Create {
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        email: String,
+        #[arg(long, default_value = "user")]
+        role: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// List users
+    List {
+        /// List soft-deleted users instead
+        #[arg(long)]
+        deleted: bool,
+    },
+    /// Change a user's role
+    SetRole { email: String, role: String },
+    /// Set a new password; read from stdin unless --password is given
+    ResetPassword {
+        email: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Move a user to the trash
+    Delete { email: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TokenCommand {
+    /// Issue a JWT for a user
+    Issue { email: String },
+    /// Validate a JWT and print its claims
+    Inspect { token: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Verify that required settings are present and the database is reachable
+    Check,
+}
+
+/// Runs a headless command and returns the process exit code.
+pub async fn run(command: Command) -> i32 {
+    let result = match command {
+        Command::Serve => serve().await.map_err(|e| e.to_string()),
+        Command::Migrate => {
+            connect_db().await;
+            println!("Migrations applied");
+            Ok(())
+        }
+        Command::User(cmd) => run_user(cmd).await,
+        Command::Token(cmd) => run_token(cmd).await,
+        Command::Config(ConfigCommand::Check) => config_check().await,
+        Command::PromoteAdmin { email } => set_role(&email, "admin").await,
+        Command::Openapi => ApiDoc::openapi().to_pretty_json().map(|json| println!("{}", json)).map_err(|e| e.to_string()),
+        Command::WebhookStub { addr, secret, status } => webhooks::run_stub(&addr, secret, status)
+            .await
+            .map_err(|e| e.to_string()),
+        Command::LdapTest { login, password } => ldap_test(&login, password).await,
+        #[cfg(feature = "dev-tools")]
+        Command::OidcMock { addr, email, name, groups } => oidc_mock::run(&addr, MockUser { email, name, groups })
+            .await
+            .map_err(|e| e.to_string()),
+        #[cfg(feature = "otel")]
+        Command::OtelStub { addr } => otel::run_collector_stub(&addr).await.map_err(|e| e.to_string()),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+async fn run_user(cmd: UserCommand) -> Result<(), String> {
+    match cmd {
+        UserCommand::Create { name, email, role, password } => {
+            if !ROLES.contains(&role.as_str()) {
+                return Err(format!("Invalid role '{}'", role));
+            }
+            let password = password_or_stdin(password)?;
+            let hashed = hash_password(&password).map_err(|e| e.to_string())?;
+            let user = NewUser { name, email, role, password };
+
+            let pool = connect_db().await;
+            let id = user_repo::insert_user(&pool, &user, &hashed)
+                .await
+                .map_err(|e| e.message)?;
+            println!("Created user '{}' with id {}", user.email, id);
+            Ok(())
+        }
+        UserCommand::List { deleted } => {
+            let pool = connect_db().await;
+            if deleted {
+                let users = user_repo::list_deleted_users(&pool).await.map_err(|e| e.to_string())?;
+                for u in users {
+                    println!("{}\t{}\t{}\t{}\tdeleted {}", u.id, u.email, u.name, u.role, u.deleted_at);
+                }
+            } else {
+                let users = user_repo::list_users(&pool).await.map_err(|e| e.to_string())?;
+                for u in users {
+                    println!("{}\t{}\t{}\t{}", u.id, u.email, u.name, u.role);
+                }
+            }
+            Ok(())
+        }
+        UserCommand::SetRole { email, role } => {
+            if !ROLES.contains(&role.as_str()) {
+                return Err(format!("Invalid role '{}'", role));
+            }
+            set_role(&email, &role).await
+        }
+        UserCommand::ResetPassword { email, password } => {
+            let password = password_or_stdin(password)?;
+            let hashed = hash_password(&password).map_err(|e| e.to_string())?;
+
+            let pool = connect_db().await;
+            match user_repo::set_password(&pool, &email, &hashed).await {
+                Ok(true) => {
+                    println!("Password updated for '{}'", email);
+                    Ok(())
+                }
+                Ok(false) => Err(format!("No active user with email '{}'", email)),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        UserCommand::Delete { email } => {
+            let pool = connect_db().await;
+            let user = user_repo::find_user_by_email(&pool, &email)
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("No active user with email '{}'", email))?;
+
+            user_repo::delete_user(&pool, user.id, None)
+                .await
+                .map_err(|e| e.to_string())?;
+            println!("User '{}' moved to trash", email);
+            Ok(())
+        }
+    }
+}
+
+async fn run_token(cmd: TokenCommand) -> Result<(), String> {
+    match cmd {
+        TokenCommand::Issue { email } => {
+            let pool = connect_db().await;
+            let user = user_repo::find_user_by_email(&pool, &email)
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("No active user with email '{}'", email))?;
+
+            let token = create_jwt(&user).map_err(|e| e.to_string())?;
+            println!("{}", token);
+            Ok(())
+        }
+        TokenCommand::Inspect { token } => {
+            dotenvy::dotenv().ok();
+            let claims = validate_jwt(&token).map_err(|e| e.to_string())?;
+            println!("{}", serde_json::to_string_pretty(&claims).map_err(|e| e.to_string())?);
+            Ok(())
+        }
+    }
+}
+
+// Shared by `user set-role` and `promote-admin`; the admin guardrails still apply.
+async fn set_role(email: &str, role: &str) -> Result<(), String> {
+    let pool = connect_db().await;
+    match user_repo::set_role(&pool, email, role).await {
+        Ok(Some(user)) => {
+            println!("User '{}' (id {}) now has role '{}'", user.email, user.id, user.role);
+            Ok(())
+        }
+        Ok(None) => Err(format!("No active user with email '{}'", email)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+async fn config_check() -> Result<(), String> {
+    dotenvy::dotenv().ok();
+    let mut problems = Vec::new();
+
+    match env::var("JWT_SECRET") {
+        Ok(s) if s.len() >= 32 => println!("ok    JWT_SECRET"),
+        Ok(_) => println!("warn  JWT_SECRET is shorter than 32 bytes"),
+        Err(_) => problems.push("JWT_SECRET not set"),
+    }
+
+    if let Ok(v) = env::var("USER_RETENTION_DAYS") {
+        if v.parse::<u32>().is_err() {
+            problems.push("USER_RETENTION_DAYS is not a number");
+        }
+    }
+
+    match env::var("DATABASE_URL") {
+        Ok(url) => match sqlx::MySqlPool::connect(&url).await {
+            Ok(_) => println!("ok    DATABASE_URL"),
+            Err(_) => problems.push("DATABASE_URL is not reachable"),
+        },
+        Err(_) => problems.push("DATABASE_URL not set"),
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems.join("; "))
+    }
+}
+
+async fn ldap_test(login: &str, password: Option<String>) -> Result<(), String> {
+    let config = LdapConfig::from_env().ok_or("LDAP_URL not set")?;
+    let password = password_or_stdin(password)?;
+
+    match ldap::authenticate(&config, login, &password).await.map_err(|e| e.message)? {
+        LdapLogin::Authenticated(identity) => {
+            let role = config.role_for_groups(&identity.groups);
+            println!("dn      {}", identity.dn);
+            println!("email   {}", identity.email);
+            println!("name    {}", identity.name);
+            for group in &identity.groups {
+                println!("group   {}", group);
+            }
+            match role {
+                Some(role) => println!("role    {} (mapped)", role),
+                None => println!("role    {} (default)", config.default_role),
+            }
+            Ok(())
+        }
+        LdapLogin::InvalidCredentials => Err("Invalid credentials".into()),
+        LdapLogin::NotInDirectory => Err(format!("No directory entry matches '{}'", login)),
+    }
+}
+
+// Keeps passwords out of shell history unless passed explicitly.
+fn password_or_stdin(password: Option<String>) -> Result<String, String> {
+    if let Some(p) = password {
+        return Ok(p);
+    }
+
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line).map_err(|e| e.to_string())?;
+    let line = line.trim_end_matches(['\r', '\n']).to_string();
+    if line.is_empty() {
+        return Err("Password cannot be empty".into());
+    }
+    Ok(line)
+}
@@ -0,0 +1,243 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::Argon2;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::utils::files::write_private;
+
+const CREDENTIAL_FILE: &str = "credentials.bin";
+const SECRET_FILE: &str = "credentials.key";
+const KEYCHAIN_SERVICE: &str = "com.my-tauri-app.app";
+const KEYCHAIN_ACCOUNT: &str = "credential-store";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// What "remember me" keeps on disk between launches.
+#[derive(Serialize, Deserialize)]
+pub struct StoredCredential {
+    pub email: String,
+    pub refresh_token: String,
+}
+
+impl fmt::Debug for StoredCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoredCredential")
+            .field("email", &self.email)
+            .field("refresh_token", &"[REDACTED]")
+            .finish()
+    }
+}
+
+/// Encrypted credential file in the Tauri app data directory.
+///
+/// The file holds a random salt, the nonce and the AES-256-GCM ciphertext. The key is
+/// derived from a secret with Argon2id and the salt. The secret is `CREDENTIAL_STORE_SECRET`
+/// if set, otherwise a random per-install secret kept in the OS keychain. Without a keychain
+/// it falls back to a file next to the credential. Files are readable by the current user only.
+pub struct CredentialStore {
+    dir: PathBuf,
+    secret: Option<Vec<u8>>, // `CREDENTIAL_STORE_SECRET`, if set
+}
+
+impl CredentialStore {
+    /// Opens the store in the app data directory, creating the directory if needed.
+    pub fn for_app(app: &AppHandle) -> Result<Self, String> {
+        let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let secret = std::env::var("CREDENTIAL_STORE_SECRET").ok().map(String::into_bytes);
+        Ok(CredentialStore { dir, secret })
+    }
+
+    /// Encrypts and writes the credential, replacing any previous one.
+    pub fn save(&self, credential: &StoredCredential) -> io::Result<()> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let cipher = Aes256Gcm::new(&self.key(&salt)?.into());
+        let plaintext = serde_json::to_vec(credential)?;
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+            .map_err(|_| io::Error::other("Failed to encrypt credentials"))?;
+
+        let mut contents = salt.to_vec();
+        contents.extend_from_slice(&nonce);
+        contents.extend_from_slice(&ciphertext);
+        write_private(&self.dir.join(CREDENTIAL_FILE), &contents)
+    }
+
+    /// Reads and decrypts the stored credential.
+    /// A missing, tampered or undecryptable file yields `None`.
+    pub fn load(&self) -> Option<StoredCredential> {
+        let contents = fs::read(self.dir.join(CREDENTIAL_FILE)).ok()?;
+        if contents.len() <= SALT_LEN + NONCE_LEN {
+            return None;
+        }
+
+        let (salt, rest) = contents.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let cipher = Aes256Gcm::new(&self.key(salt).ok()?.into());
+        let plaintext = cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+        serde_json::from_slice(&plaintext).ok()
+    }
+
+    /// Removes the stored credential, if any.
+    pub fn clear(&self) {
+        let _ = fs::remove_file(self.dir.join(CREDENTIAL_FILE));
+    }
+
+    // Derives the encryption key from the secret; a password hash, since the secret may be a passphrase.
+    fn key(&self, salt: &[u8]) -> io::Result<[u8; 32]> {
+        let secret = match &self.secret {
+            Some(s) => s.clone(),
+            None => self.install_secret()?,
+        };
+
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(&secret, salt, &mut key)
+            .map_err(|e| io::Error::other(format!("Failed to derive the credential key: {}", e)))?;
+        Ok(key)
+    }
+
+    // The per-install secret, from the OS keychain if there is one.
+    fn install_secret(&self) -> io::Result<Vec<u8>> {
+        let path = self.dir.join(SECRET_FILE);
+        match keychain_secret() {
+            Ok(secret) => {
+                // Written by versions that kept the secret next to the credential
+                let _ = fs::remove_file(path);
+                Ok(secret)
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "OS keychain unavailable, keeping the credential secret in a file");
+                file_secret(&path)
+            }
+        }
+    }
+}
+
+// Reads the secret from the keychain, generating it on first use.
+fn keychain_secret() -> keyring::Result<Vec<u8>> {
+    let entry = keyring::Entry::new(KEYCHAIN_SERVICE, KEYCHAIN_ACCOUNT)?;
+    match entry.get_secret() {
+        Ok(secret) => Ok(secret),
+        Err(keyring::Error::NoEntry) => {
+            let secret = random_secret();
+            entry.set_secret(&secret)?;
+            Ok(secret)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// Reads the secret from `path`, generating it on first use.
+fn file_secret(path: &Path) -> io::Result<Vec<u8>> {
+    match fs::read(path) {
+        Ok(secret) => Ok(secret),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let secret = random_secret();
+            write_private(path, &secret)?;
+            Ok(secret)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn random_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::auth::random_token;
+
+    // A store in a fresh directory with a fixed secret, so the keychain is never touched.
+    fn store(secret: &str) -> CredentialStore {
+        let dir = std::env::temp_dir().join(random_token(8));
+        fs::create_dir_all(&dir).unwrap();
+        CredentialStore { dir, secret: Some(secret.as_bytes().to_vec()) }
+    }
+
+    fn credential() -> StoredCredential {
+        StoredCredential { email: "ada@example.org".to_string(), refresh_token: "refresh".to_string() }
+    }
+
+    #[test]
+    fn saved_credential_loads_back() {
+        let store = store("secret");
+        assert!(store.load().is_none());
+
+        store.save(&credential()).unwrap();
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.email, "ada@example.org");
+        assert_eq!(loaded.refresh_token, "refresh");
+
+        store.clear();
+        assert!(store.load().is_none());
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn file_does_not_contain_the_token() {
+        let store = store("secret");
+        store.save(&credential()).unwrap();
+
+        let contents = fs::read(store.dir.join(CREDENTIAL_FILE)).unwrap();
+        assert!(!contents.windows(b"refresh".len()).any(|w| w == b"refresh"));
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn tampered_file_is_rejected() {
+        let store = store("secret");
+        store.save(&credential()).unwrap();
+        let path = store.dir.join(CREDENTIAL_FILE);
+        let original = fs::read(&path).unwrap();
+
+        // Salt, nonce and ciphertext are all covered
+        for index in [0, SALT_LEN, original.len() - 1] {
+            let mut tampered = original.clone();
+            tampered[index] ^= 1;
+            fs::write(&path, &tampered).unwrap();
+            assert!(store.load().is_none(), "byte {} flipped", index);
+        }
+
+        fs::write(&path, &original[..SALT_LEN + NONCE_LEN]).unwrap();
+        assert!(store.load().is_none());
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn other_secret_cannot_decrypt() {
+        let store = store("secret");
+        store.save(&credential()).unwrap();
+
+        let other = CredentialStore { dir: store.dir.clone(), secret: Some(b"other".to_vec()) };
+        assert!(other.load().is_none());
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn every_save_uses_a_new_salt() {
+        let store = store("secret");
+        store.save(&credential()).unwrap();
+        let first = fs::read(store.dir.join(CREDENTIAL_FILE)).unwrap();
+        store.save(&credential()).unwrap();
+        let second = fs::read(store.dir.join(CREDENTIAL_FILE)).unwrap();
+
+        assert_ne!(first[..SALT_LEN], second[..SALT_LEN]);
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+}
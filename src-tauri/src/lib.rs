@@ -1,9 +1,14 @@
+pub mod cli;
+pub mod utils;
+
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
+#[cfg(feature = "desktop")]
 #[tauri::command]
 fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+#[cfg(feature = "desktop")]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
@@ -1,27 +1,136 @@
 
-mod utils;
+mod credential_store;
 mod tauri_handlers;
 use std::sync::Arc;
+use clap::Parser;
+use my_tauri_app_lib::cli::{self, Cli};
+use std::time::Duration;
+use my_tauri_app_lib::utils;
+use crate::utils::config::ApiMode;
 use crate::utils::server::run_server;
+use crate::utils::api_client::{ApiClient, FeedItem};
+use crate::utils::events::{EventBus, SequencedEvent};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::broadcast::error::RecvError;
 
-use tauri_handlers::{register_tauri, login_tauri, fetch_all_users_tauri, create_user_tauri, update_user_tauri, delete_user_tauri, AppState};
+/// How long to wait before reconnecting to the remote change feed, or checking for a session again.
+const FEED_RETRY: Duration = Duration::from_secs(5);
+
+use tauri_handlers::{register_tauri, login_tauri, login_oidc_tauri, logout_tauri, current_session_tauri, fetch_all_users_tauri, get_user_tauri, create_user_tauri, update_user_tauri, patch_user_tauri, delete_user_tauri, fetch_deleted_users_tauri, restore_user_tauri, AppState, Backend};
 
 
 
 #[tokio::main]
 async fn main() {
-    let pool = run_server().await;
+    utils::telemetry::init_tracing();
+
+    // Headless subcommands run instead of the desktop app
+    if let Some(command) = Cli::parse().command {
+        std::process::exit(cli::run(command).await);
+    }
+
+    // Changes from the Tauri commands and the embedded REST API both land here
+    let events = EventBus::new();
+
+    // Either host the REST API in-process or talk to a remote server over HTTP
+    let backend = match ApiMode::from_env() {
+        ApiMode::Embedded => Backend::Local(Arc::new(run_server(events.clone()).await)),
+        ApiMode::Remote(url) => {
+            tracing::info!(%url, "Using remote API");
+            Backend::Remote(ApiClient::new(url))
+        }
+    };
+
+    let mut changes = events.subscribe();
+    let remote = match &backend {
+        Backend::Remote(api) => Some((api.clone(), events.clone())),
+        Backend::Local(_) => None,
+    };
 
     tauri::Builder::default()
-        .manage(AppState { db: Arc::new(pool) })
+        .plugin(tauri_plugin_opener::init())
+        .manage(AppState::new(backend, events))
+        .setup(move |app| {
+            // Other clients change users on the server too; their changes come through its feed
+            if let Some((api, events)) = remote {
+                tauri::async_runtime::spawn(follow_remote_feed(app.handle().clone(), api, events));
+            }
+
+            // Forward user changes to the webview as `user-created` / `user-updated` / `user-deleted`
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                loop {
+                    match changes.recv().await {
+                        Ok(SequencedEvent { event, .. }) => {
+                            if let Err(e) = handle.emit(event.name(), event.user()) {
+                                tracing::warn!(event = event.name(), error = %e, "Failed to emit user event");
+                            }
+                        }
+                        // Missed events only mean a stale list until the next one arrives
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             register_tauri,
             login_tauri,
+            login_oidc_tauri,
+            logout_tauri,
+            current_session_tauri,
             create_user_tauri,
             fetch_all_users_tauri,
+            get_user_tauri,
             update_user_tauri,
-            delete_user_tauri
+            patch_user_tauri,
+            delete_user_tauri,
+            fetch_deleted_users_tauri,
+            restore_user_tauri
         ])
         .run(tauri::generate_context!())
         .expect("error running Tauri app");
 }
+
+// Republishes the remote server's change feed on the local bus while someone is signed in.
+// The feed only carries what the signed-in user may see, so it is dropped when the session changes.
+// This app's own writes arrive a second time this way; the webview applies events idempotently.
+async fn follow_remote_feed(handle: AppHandle, api: ApiClient, events: EventBus) {
+    let mut last_event_id = None;
+    loop {
+        let Some(token) = handle.state::<AppState>().session_token() else {
+            tokio::time::sleep(FEED_RETRY).await;
+            continue;
+        };
+
+        match api.user_events(&token, last_event_id).await {
+            Ok(mut feed) => loop {
+                let item = feed.next().await;
+                // Keep-alives arrive every 15 seconds, so a logout is noticed soon
+                if handle.state::<AppState>().session_token().as_ref() != Some(&token) {
+                    break;
+                }
+                match item {
+                    Ok(Some(FeedItem::Event(SequencedEvent { id, event }))) => {
+                        last_event_id = Some(id);
+                        events.publish(event);
+                    }
+                    Ok(Some(FeedItem::Reset)) => {
+                        if let Err(e) = handle.emit("users-reset", ()) {
+                            tracing::warn!(error = %e, "Failed to emit users-reset");
+                        }
+                    }
+                    Ok(Some(FeedItem::KeepAlive)) => {}
+                    Ok(None) => break,
+                    Err(e) => {
+                        tracing::warn!(error = %e.message, "Remote change feed interrupted");
+                        break;
+                    }
+                }
+            },
+            Err(e) => tracing::warn!(error = %e.message, "Could not open the remote change feed"),
+        }
+        tokio::time::sleep(FEED_RETRY).await;
+    }
+}
@@ -1,167 +1,543 @@
-use tauri::State;
-use std::sync::Arc;
+use tauri::{AppHandle, State};
+use tauri_plugin_opener::OpenerExt;
+use tokio::net::TcpListener;
+use std::fmt;
+use std::sync::{Arc, Mutex};
 use sqlx::MySqlPool;
-use bcrypt::{hash, verify, DEFAULT_COST};
+use chrono::{DateTime, Duration, Utc};
 
-use crate::utils::models::{PublicUser, NewUser, UpdateUser, LoginRequest, LoginResponse, User};
-use crate::utils::auth::{create_jwt, validate_jwt};
+use crate::utils::models::{PublicUser, DeletedUser, NewUser, UpdateUser, PatchUser, LoginRequest, LoginResponse, SessionInfo, User};
+use crate::utils::auth::{create_jwt, hash_password, TOKEN_TTL_HOURS};
+use crate::utils::metrics::record_login;
+use crate::utils::telemetry::new_request_id;
+use crate::utils::app_error::AppError;
+use crate::utils::events::{EventBus, UserEvent};
+use crate::utils::user_repo::{self, UpdateOutcome};
+use crate::utils::permissions::{authorize_patch, REGISTERED_ROLE};
+use crate::utils::validation::{validate_patch, validate_role};
+use crate::utils::api_client::ApiClient;
+use crate::utils::refresh_tokens;
+use crate::utils::oidc::{self, OidcClient};
+use crate::utils::ldap;
+use crate::utils::config::LdapConfig;
+use crate::credential_store::{CredentialStore, StoredCredential};
 
+/// Where the Tauri commands read and write users.
+pub enum Backend {
+    /// Straight to MySQL; needs database credentials on this machine.
+    Local(Arc<MySqlPool>),
+    /// Through a remote server's REST API; the server does all authorization.
+    Remote(ApiClient),
+}
+
+/// Identity established by `login_tauri`. Commands are authorized from it,
+/// so the JWT never has to reach the webview.
 #[derive(Clone)]
+pub struct Session {
+    pub user_id: Option<i32>, // local mode only; the server checks its own token in remote mode
+    pub name: String,
+    pub email: String,
+    pub role: String,
+    pub token: String, // sent to the server in remote mode
+    pub refresh_token: Option<String>, // set when "remember me" was ticked
+    pub expires_at: DateTime<Utc>,
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("user_id", &self.user_id)
+            .field("name", &self.name)
+            .field("email", &self.email)
+            .field("role", &self.role)
+            .field("token", &"[REDACTED]")
+            .field("refresh_token", &self.refresh_token.as_ref().map(|_| "[REDACTED]"))
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+impl Session {
+    // A session for a user signed in on this machine; the email is the account's, not the login typed.
+    fn local(user: User, token: String, refresh_token: Option<String>) -> Self {
+        Session {
+            user_id: Some(user.id),
+            name: user.name,
+            email: user.email,
+            role: user.role,
+            token,
+            refresh_token,
+            expires_at: Utc::now() + Duration::hours(TOKEN_TTL_HOURS),
+        }
+    }
+
+    // A session the remote server signed in.
+    fn remote(login: LoginResponse) -> Self {
+        Session {
+            user_id: None,
+            name: login.name,
+            email: login.email,
+            role: login.role,
+            token: login.token,
+            refresh_token: login.refresh_token,
+            expires_at: Utc::now() + Duration::hours(TOKEN_TTL_HOURS),
+        }
+    }
+
+    fn info(&self) -> SessionInfo {
+        SessionInfo {
+            name: self.name.clone(),
+            email: self.email.clone(),
+            role: self.role.clone(),
+            expires_at: self.expires_at,
+        }
+    }
+}
+
 pub struct AppState {
-    pub db: Arc<MySqlPool>,
+    pub backend: Backend,
+    pub session: Mutex<Option<Session>>,
+    /// Local changes are published here; `main` forwards them to the webview.
+    pub events: EventBus,
+}
+
+impl AppState {
+    pub fn new(backend: Backend, events: EventBus) -> Self {
+        AppState {
+            backend,
+            session: Mutex::new(None),
+            events,
+        }
+    }
+
+    // The current session; an expired one is dropped on first use.
+    fn session(&self) -> Result<Session, AppError> {
+        let mut session = self.session.lock().unwrap();
+        match session.as_ref() {
+            Some(s) if s.expires_at > Utc::now() => Ok(s.clone()),
+            Some(_) => {
+                *session = None;
+                Err(AppError::unauthorized("Session expired, please log in again"))
+            }
+            None => Err(AppError::unauthorized("Not logged in")),
+        }
+    }
+
+    /// The token of the current session, if one is signed in and not expired.
+    pub fn session_token(&self) -> Option<String> {
+        self.session().ok().map(|s| s.token)
+    }
+
+    // The current session, which must belong to an admin.
+    fn admin_session(&self, action: &str) -> Result<Session, AppError> {
+        let session = self.session()?;
+        if session.role != "admin" {
+            return Err(AppError::forbidden(format!("Access denied: only admins can {}", action)));
+        }
+        Ok(session)
+    }
 }
 
 // Register a new user
 #[tauri::command]
+#[tracing::instrument(skip_all, err, fields(request_id = %new_request_id()))]
 pub async fn register_tauri(user: NewUser, state: State<'_, AppState>) -> Result<(), String> {
-    
+    let db = match &state.backend {
+        Backend::Local(db) => db,
+        Backend::Remote(api) => return api.register(&user).await.map_err(|e| e.message),
+    };
+    let user = NewUser { role: REGISTERED_ROLE.to_string(), ..user };
 
     // Hash password using bcrypt
-    let hashed_password = hash(user.password.as_bytes(), DEFAULT_COST)
+    let hashed_password = hash_password(&user.password)
         .map_err(|e| e.to_string())?;
 
     // Insert new user into the database
-    sqlx::query!(
-        "INSERT INTO users (name, email, password, role) VALUES (?, ?, ?, ?)",
-        user.name,
-        user.email,
-        hashed_password,
-        user.role
-    )
-    .execute(&*state.db)
-    .await
-    .map_err(|e| e.to_string())?;
+    let id = user_repo::insert_user(db, &user, &hashed_password)
+        .await
+        .map_err(|e| e.message)?;
 
+    state.events.publish_created(db, id).await;
     Ok(())
 }
 
 
-// Login a user
+// Login a user and start a session
 #[tauri::command]
+#[tracing::instrument(skip_all, err, fields(request_id = %new_request_id()))]
 pub async fn login_tauri(
     form: LoginRequest,
+    app: AppHandle,
     state: State<'_, AppState>,
-) -> Result<LoginResponse, String> {
+) -> Result<SessionInfo, String> {
     if form.email.trim().is_empty() || form.password.trim().is_empty() {
         return Err("Email and password cannot be empty.".into());
     }
 
+    let session = match &state.backend {
+        Backend::Local(db) => {
+            // Directory users are checked against LDAP, everyone else against the local password
+            let ldap = LdapConfig::from_env();
+            let login = ldap::password_login(db, ldap.as_ref(), &form.email, &form.password).await;
+            record_login(matches!(login, Ok(Some(_))));
+            let user = match login.map_err(|e| e.message)? {
+                Some((u, created)) => {
+                    if created {
+                        state.events.publish_created(db, u.id as u64).await;
+                    }
+                    u
+                }
+                None => return Err("Invalid credentials.".into()),
+            };
 
-    // Fetch user from the database by email
-    let user = sqlx::query_as!(
-        User,
-        "SELECT id, name, email, role, password FROM users WHERE email = ?",
-        form.email
-    )
-    .fetch_optional(&*state.db)
-    .await
-    .map_err(|e| e.to_string())?;
-
-    // Verify password and generate JWT token if valid
-    match user {
-        Some(u) => {
-            let is_valid = verify(form.password.as_bytes(), &u.password)
-                .map_err(|e| e.to_string())?;
-            if is_valid {
-                let token = create_jwt(&u).map_err(|e| e.to_string())?;
-                Ok(LoginResponse { token, name: u.name,  role: u.role, })
+            // Generate the JWT for the authenticated user
+            let token = create_jwt(&user).map_err(|e| e.to_string())?;
+            let refresh_token = if form.remember_me {
+                Some(refresh_tokens::issue(db, user.id).await.map_err(|e| e.to_string())?)
             } else {
-                Err("Invalid credentials.".into())
+                None
+            };
+            Session::local(user, token, refresh_token)
+        }
+        Backend::Remote(api) => Session::remote(api.login(&form).await.map_err(|e| e.message)?),
+    };
+
+    start_session(&app, &state, session)
+}
+
+// Sign in through the identity provider in the system browser
+#[tauri::command]
+#[tracing::instrument(skip_all, err, fields(request_id = %new_request_id()))]
+pub async fn login_oidc_tauri(
+    remember_me: bool,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<SessionInfo, String> {
+    // The provider sends the browser back to a one-off listener on this machine (RFC 8252)
+    let listener = TcpListener::bind("127.0.0.1:0").await.map_err(|e| e.to_string())?;
+    let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+    let redirect_uri = format!("http://127.0.0.1:{}/callback", port);
+
+    let session = match &state.backend {
+        Backend::Local(db) => {
+            let oidc = OidcClient::from_env().ok_or_else(|| AppError::sso_not_configured().message)?;
+            let request = oidc.authorization_request(&redirect_uri).await.map_err(|e| e.message)?;
+            open_browser(&app, &request.url)?;
+            let code = oidc::receive_callback(listener, &request.state).await.map_err(|e| e.message)?;
+
+            let (user, created) = oidc.finish(db, &request, &code).await.map_err(|e| e.message)?;
+            if created {
+                state.events.publish_created(db, user.id as u64).await;
             }
+            let token = create_jwt(&user).map_err(|e| e.to_string())?;
+            let refresh_token = if remember_me {
+                Some(refresh_tokens::issue(db, user.id).await.map_err(|e| e.to_string())?)
+            } else {
+                None
+            };
+            Session::local(user, token, refresh_token)
+        }
+        Backend::Remote(api) => {
+            let started = api.oidc_start(&redirect_uri).await.map_err(|e| e.message)?;
+            open_browser(&app, &started.authorization_url)?;
+            let code = oidc::receive_callback(listener, &started.state).await.map_err(|e| e.message)?;
+
+            Session::remote(api.oidc_exchange(&code, &started.state, remember_me).await.map_err(|e| e.message)?)
         }
-        None => Err("Invalid credentials.".into()),
+    };
+
+    start_session(&app, &state, session)
+}
+
+fn open_browser(app: &AppHandle, url: &str) -> Result<(), String> {
+    app.opener().open_url(url, None::<&str>).map_err(|e| format!("Could not open the browser: {}", e))
+}
+
+// Makes `session` current and remembers its refresh token on disk, or forgets a previous one
+fn start_session(app: &AppHandle, state: &AppState, session: Session) -> Result<SessionInfo, String> {
+    let store = CredentialStore::for_app(app)?;
+    match &session.refresh_token {
+        Some(t) => store
+            .save(&StoredCredential { email: session.email.clone(), refresh_token: t.clone() })
+            .map_err(|e| e.to_string())?,
+        None => store.clear(),
     }
+
+    let info = session.info();
+    *state.session.lock().unwrap() = Some(session);
+
+    Ok(info)
+}
+
+// Return the current session, silently restoring a remembered one if needed
+#[tauri::command]
+#[tracing::instrument(skip_all, err, fields(request_id = %new_request_id()))]
+pub async fn current_session_tauri(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<Option<SessionInfo>, String> {
+    if let Ok(session) = state.session() {
+        return Ok(Some(session.info()));
+    }
+
+    let store = CredentialStore::for_app(&app)?;
+    let stored = match store.load() {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+
+    // Redeeming rotates the refresh token, so the new one replaces the stored one
+    let redeemed = match &state.backend {
+        Backend::Local(db) => match refresh_tokens::redeem(db, &stored.refresh_token).await {
+            Ok(Some((user, refresh_token))) => {
+                let token = create_jwt(&user).map_err(|e| e.to_string())?;
+                Some(Session::local(user, token, Some(refresh_token)))
+            }
+            Ok(None) => None,
+            Err(e) => return Err(e.to_string()),
+        },
+        Backend::Remote(api) => match api.refresh(&stored.refresh_token).await {
+            Ok(r) => r.refresh_token.is_some().then(|| Session::remote(r)),
+            Err(e) if e.code == "UNAUTHORIZED" => None,
+            Err(e) => return Err(e.message),
+        },
+    };
+
+    // Revoked (e.g. after a password change) or expired: forget it
+    match redeemed {
+        Some(session) => start_session(&app, &state, session).map(Some),
+        None => {
+            store.clear();
+            Ok(None)
+        }
+    }
+}
+
+// End the current session and forget any remembered credential
+#[tauri::command]
+#[tracing::instrument(skip_all, err, fields(request_id = %new_request_id()))]
+pub async fn logout_tauri(app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    let session = state.session.lock().unwrap().take();
+    CredentialStore::for_app(&app)?.clear();
+
+    // Revoke the refresh token so a copied credential file is useless
+    if let Some(refresh_token) = session.and_then(|s| s.refresh_token) {
+        match &state.backend {
+            Backend::Local(db) => refresh_tokens::revoke(db, &refresh_token)
+                .await
+                .map_err(|e| e.to_string())?,
+            Backend::Remote(api) => api.logout(&refresh_token).await.map_err(|e| e.message)?,
+        }
+    }
+
+    Ok(())
 }
 
 
 // Create a new user (admin only)
 #[tauri::command]
+#[tracing::instrument(skip_all, err, fields(request_id = %new_request_id()))]
 pub async fn create_user_tauri(
     user: NewUser,
-    token: String,
     state: State<'_, AppState>
 ) -> Result<(), String> {
-    // Validate JWT token and check role
-    let claims = validate_jwt(&token).map_err(|_| "Invalid or expired token")?;
-    if claims.role != "admin" {
-        return Err("Access denied: only admins can create users".to_string());
-    }
+    // Check the session role
+    let session = state.admin_session("create users").map_err(|e| e.message)?;
+    validate_role(&user.role).map_err(|e| e.message)?;
+
+    let db = match &state.backend {
+        Backend::Local(db) => db,
+        Backend::Remote(api) => {
+            let created = api.create_user(&session.token, &user).await.map_err(|e| e.message)?;
+            state.events.publish(UserEvent::Created(created));
+            return Ok(());
+        }
+    };
 
     // Hash password using bcrypt
-    let hashed_password = hash(user.password.as_bytes(), DEFAULT_COST)
+    let hashed_password = hash_password(&user.password)
         .map_err(|e| e.to_string())?;
 
     // Insert the new user into the database
-    sqlx::query!(
-        "INSERT INTO users (name, email, password, role) VALUES (?, ?, ?, ?)",
-        user.name,
-        user.email,
-        hashed_password,
-        user.role
-    )
-    .execute(&*state.db)
-    .await
-    .map_err(|e| e.to_string())?;
+    let id = user_repo::insert_user(db, &user, &hashed_password)
+        .await
+        .map_err(|e| e.message)?;
 
+    state.events.publish_created(db, id).await;
     Ok(())
 }
 
 // Fetch all users
 #[tauri::command]
+#[tracing::instrument(skip_all, err, fields(request_id = %new_request_id()))]
 pub async fn fetch_all_users_tauri(state: State<'_, AppState>) -> Result<Vec<PublicUser>, String> {
-    sqlx::query_as!(PublicUser, "SELECT id, name, email, role FROM users")
-        .fetch_all(&*state.db)
-        .await
-        .map_err(|e| e.to_string())
+    let session = state.session().map_err(|e| e.message)?;
+
+    match &state.backend {
+        Backend::Local(db) => user_repo::list_users(db)
+            .await
+            .map_err(|e| e.to_string()),
+        Backend::Remote(api) => api.list_users(&session.token).await.map_err(|e| e.message),
+    }
+}
+
+// Fetch a single user by id
+#[tauri::command]
+#[tracing::instrument(skip_all, err, fields(request_id = %new_request_id()))]
+pub async fn get_user_tauri(id: i32, state: State<'_, AppState>) -> Result<PublicUser, AppError> {
+    let session = state.session()?;
+
+    match &state.backend {
+        Backend::Local(db) => user_repo::find_public_user(&**db, id)
+            .await?
+            .ok_or_else(AppError::user_not_found),
+        Backend::Remote(api) => api.get_user(&session.token, id).await,
+    }
 }
 
 // Update user information (admin only)
 #[tauri::command]
+#[tracing::instrument(skip_all, err, fields(request_id = %new_request_id()))]
 pub async fn update_user_tauri(
     user: UpdateUser,
-    token: String,
     state: State<'_, AppState>
-) -> Result<(), String> {
-    // Validate JWT token and check role
-    let claims = validate_jwt(&token).map_err(|_| "Invalid or expired token")?;
-    if claims.role != "admin" {
-        return Err("Access denied: only admins can update users".to_string());
+) -> Result<PublicUser, AppError> {
+    // Check the session role
+    let session = state.admin_session("update users")?;
+    validate_role(&user.role)?;
+
+    let db = match &state.backend {
+        Backend::Local(db) => db,
+        Backend::Remote(api) => {
+            let updated = api.update_user(&session.token, &user).await?;
+            state.events.publish(UserEvent::Updated(updated.clone()));
+            return Ok(updated);
+        }
+    };
+
+    // The version the caller edited must still be current
+    let expected_version = user
+        .version
+        .ok_or_else(|| AppError::precondition_required("The user's version is required"))?;
+
+    // Update the user in the database and return the updated record
+    match user_repo::update_user(db, &user, expected_version, session.user_id).await? {
+        UpdateOutcome::Updated(updated) => {
+            state.events.publish(UserEvent::Updated(updated.clone()));
+            Ok(updated)
+        }
+        UpdateOutcome::NotFound => Err(AppError::user_not_found()),
+        UpdateOutcome::Stale(current) => Err(AppError::conflict(current)),
     }
+}
+
+// Partially update a user; absent fields are left unchanged
+#[tauri::command]
+#[tracing::instrument(skip_all, err, fields(request_id = %new_request_id()))]
+pub async fn patch_user_tauri(
+    id: i32,
+    patch: PatchUser,
+    state: State<'_, AppState>
+) -> Result<PublicUser, AppError> {
+    let session = state.session()?;
+
+    let db = match &state.backend {
+        Backend::Local(db) => db,
+        Backend::Remote(api) => {
+            let updated = api.patch_user(&session.token, id, &patch).await?;
+            state.events.publish(UserEvent::Updated(updated.clone()));
+            return Ok(updated);
+        }
+    };
 
+    // Validate each field, then check it against the session role
+    validate_patch(&patch)?;
+    authorize_patch(&session.role, &patch)?;
 
-    // Update the user in the database
-    sqlx::query!(
-        "UPDATE users SET name = ?, email = ?, role = ? WHERE id = ?",
-        user.name,
-        user.email,
-        user.role,
-        user.id
-    )
-    .execute(&*state.db)
-    .await
-    .map_err(|e| format!("DB error: {}", e))?;
+    // The version the caller edited must still be current
+    let expected_version = patch
+        .version
+        .ok_or_else(|| AppError::precondition_required("The user's version is required"))?;
 
-    Ok(())
+    match user_repo::patch_user(db, id, &patch, expected_version, session.user_id).await? {
+        UpdateOutcome::Updated(updated) => {
+            state.events.publish(UserEvent::Updated(updated.clone()));
+            Ok(updated)
+        }
+        UpdateOutcome::NotFound => Err(AppError::user_not_found()),
+        UpdateOutcome::Stale(current) => Err(AppError::conflict(current)),
+    }
 }
 
 // Delete a user (admin only)
 #[tauri::command]
+#[tracing::instrument(skip_all, err, fields(request_id = %new_request_id()))]
 pub async fn delete_user_tauri(
     id: i32,
-    token: String,
     state: State<'_, AppState>
-) -> Result<(), String> {
-    // Validate JWT token and check role
-    let claims = validate_jwt(&token).map_err(|_| "Invalid or expired token")?;
-    if claims.role != "admin" {
-        return Err("Access denied: only admins can delete users".to_string());
-    }
+) -> Result<(), AppError> {
+    // Check the session role
+    let session = state.admin_session("delete users")?;
+
+    let db = match &state.backend {
+        Backend::Local(db) => db,
+        Backend::Remote(api) => {
+            // The server answers 204, so fetch the user first for the event
+            let user = api.get_user(&session.token, id).await?;
+            api.delete_user(&session.token, id).await?;
+            state.events.publish(UserEvent::Deleted(user));
+            return Ok(());
+        }
+    };
 
     // Delete the user from the database
-    sqlx::query!("DELETE FROM users WHERE id = ?", id)
-        .execute(&*state.db)
-        .await
-        .map_err(|e| e.to_string())?;
+    let user = user_repo::delete_user(db, id, session.user_id)
+        .await?
+        .ok_or_else(AppError::user_not_found)?;
 
+    state.events.publish(UserEvent::Deleted(user));
     Ok(())
 }
+
+// List soft-deleted users (admin only)
+#[tauri::command]
+#[tracing::instrument(skip_all, err, fields(request_id = %new_request_id()))]
+pub async fn fetch_deleted_users_tauri(
+    state: State<'_, AppState>
+) -> Result<Vec<DeletedUser>, AppError> {
+    // Check the session role
+    let session = state.admin_session("view deleted users")?;
+
+    match &state.backend {
+        Backend::Local(db) => Ok(user_repo::list_deleted_users(db).await?),
+        Backend::Remote(api) => api.list_deleted_users(&session.token).await,
+    }
+}
+
+// Restore a soft-deleted user (admin only)
+#[tauri::command]
+#[tracing::instrument(skip_all, err, fields(request_id = %new_request_id()))]
+pub async fn restore_user_tauri(
+    id: i32,
+    state: State<'_, AppState>
+) -> Result<PublicUser, AppError> {
+    // Check the session role
+    let session = state.admin_session("restore users")?;
+
+    let db = match &state.backend {
+        Backend::Local(db) => db,
+        Backend::Remote(api) => {
+            let user = api.restore_user(&session.token, id).await?;
+            state.events.publish(UserEvent::Created(user.clone()));
+            return Ok(user);
+        }
+    };
+
+    let user = user_repo::restore_user(db, id)
+        .await?
+        .ok_or_else(AppError::user_not_found)?;
+
+    // A restored user reappears in the list just like a new one
+    state.events.publish(UserEvent::Created(user.clone()));
+    Ok(user)
+}
@@ -0,0 +1,370 @@
+use actix_web::http::StatusCode;
+use reqwest::{header, Client, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::utils::app_error::AppError;
+use crate::utils::events::{SequencedEvent, UserEvent};
+use crate::utils::models::{
+    DeletedUser, LoginRequest, LoginResponse, NewUser, OidcExchangeRequest, OidcStartRequest,
+    OidcStartResponse, PatchUser, PublicUser, RefreshRequest, ReplaceUser, UpdateUser,
+};
+
+/// Typed HTTP client for the `/auth` and `/api/v1/users` routes of a remote server.
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    http: Client,
+    base_url: String,
+}
+
+/// What the server's change feed sends.
+#[derive(Debug)]
+pub enum FeedItem {
+    Event(SequencedEvent),
+    /// Missed events are no longer kept; the user list has to be fetched again.
+    Reset,
+    /// A comment the server sends to keep the connection open.
+    KeepAlive,
+}
+
+/// An open connection to the server's change feed, `/api/v1/users/events`.
+pub struct EventStream {
+    resp: Response,
+    buf: Vec<u8>,
+}
+
+impl EventStream {
+    /// Waits for the next item; `None` once the server closes the stream.
+    pub async fn next(&mut self) -> Result<Option<FeedItem>, AppError> {
+        loop {
+            if let Some(end) = self.buf.windows(2).position(|w| w == b"\n\n") {
+                let frame: Vec<u8> = self.buf.drain(..end + 2).collect();
+                match parse_frame(&String::from_utf8_lossy(&frame)) {
+                    Some(item) => return Ok(Some(item)),
+                    None => continue,
+                }
+            }
+            match self.resp.chunk().await {
+                Ok(Some(bytes)) => self.buf.extend_from_slice(&bytes),
+                Ok(None) => return Ok(None),
+                Err(e) => return Err(AppError::internal(format!("Change feed interrupted: {}", e))),
+            }
+        }
+    }
+}
+
+/// JSON error body produced by `AppError` on the server.
+#[derive(Deserialize)]
+struct ErrorBody {
+    code: String,
+    message: String,
+    current: Option<PublicUser>,
+}
+
+impl ApiClient {
+    /// Constructs a new `ApiClient` for the server at `base_url` (e.g. `https://api.example.com`).
+    pub fn new(base_url: impl Into<String>) -> Self {
+        ApiClient {
+            http: Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Registers a new user.
+    pub async fn register(&self, user: &NewUser) -> Result<(), AppError> {
+        let req = self.http.post(self.url("/auth/register")).json(user);
+        send(req).await.map(drop)
+    }
+
+    /// Logs in and returns the issued token.
+    pub async fn login(&self, form: &LoginRequest) -> Result<LoginResponse, AppError> {
+        let req = self.http.post(self.url("/auth/login")).json(form);
+        json(send(req).await?).await
+    }
+
+    /// Exchanges a refresh token for a new token and a rotated refresh token.
+    pub async fn refresh(&self, refresh_token: &str) -> Result<LoginResponse, AppError> {
+        let body = RefreshRequest { refresh_token: refresh_token.to_string() };
+        let req = self.http.post(self.url("/auth/refresh")).json(&body);
+        json(send(req).await?).await
+    }
+
+    /// Opens the change feed, resuming after `last_event_id` if given.
+    pub async fn user_events(&self, token: &str, last_event_id: Option<u64>) -> Result<EventStream, AppError> {
+        let mut req = self.http.get(self.url("/api/v1/users/events")).bearer_auth(token);
+        if let Some(id) = last_event_id {
+            req = req.header("Last-Event-ID", id.to_string());
+        }
+        Ok(EventStream { resp: send(req).await?, buf: Vec::new() })
+    }
+
+    /// Revokes a refresh token on the server.
+    pub async fn logout(&self, refresh_token: &str) -> Result<(), AppError> {
+        let body = RefreshRequest { refresh_token: refresh_token.to_string() };
+        let req = self.http.post(self.url("/auth/logout")).json(&body);
+        send(req).await.map(drop)
+    }
+
+    /// Starts a single sign-on whose code the caller receives on `redirect_uri`.
+    pub async fn oidc_start(&self, redirect_uri: &str) -> Result<OidcStartResponse, AppError> {
+        let body = OidcStartRequest { redirect_uri: redirect_uri.to_string() };
+        let req = self.http.post(self.url("/auth/oidc/start")).json(&body);
+        json(send(req).await?).await
+    }
+
+    /// Finishes a single sign-on with the code the identity provider returned.
+    pub async fn oidc_exchange(&self, code: &str, state: &str, remember_me: bool) -> Result<LoginResponse, AppError> {
+        let body = OidcExchangeRequest { code: code.to_string(), state: state.to_string(), remember_me };
+        let req = self.http.post(self.url("/auth/oidc/exchange")).json(&body);
+        json(send(req).await?).await
+    }
+
+    /// Lists active users.
+    pub async fn list_users(&self, token: &str) -> Result<Vec<PublicUser>, AppError> {
+        let req = self.http.get(self.url("/api/v1/users")).bearer_auth(token);
+        json(send(req).await?).await
+    }
+
+    /// Fetches a single user by id.
+    pub async fn get_user(&self, token: &str, id: i32) -> Result<PublicUser, AppError> {
+        let req = self.http.get(self.url(&format!("/api/v1/users/{}", id))).bearer_auth(token);
+        json(send(req).await?).await
+    }
+
+    /// Creates a user (admin only) and returns it.
+    pub async fn create_user(&self, token: &str, user: &NewUser) -> Result<PublicUser, AppError> {
+        let req = self.http.post(self.url("/api/v1/users")).bearer_auth(token).json(user);
+        json(send(req).await?).await
+    }
+
+    /// Replaces a user's fields; `user.version` is sent as `If-Match`.
+    pub async fn update_user(&self, token: &str, user: &UpdateUser) -> Result<PublicUser, AppError> {
+        let body = ReplaceUser { name: user.name.clone(), email: user.email.clone(), role: user.role.clone() };
+        let req = self.http.put(self.url(&format!("/api/v1/users/{}", user.id))).bearer_auth(token).json(&body);
+        json(send(if_match(req, user.version)).await?).await
+    }
+
+    /// Applies a merge patch; `patch.version` is sent as `If-Match`.
+    pub async fn patch_user(&self, token: &str, id: i32, patch: &PatchUser) -> Result<PublicUser, AppError> {
+        let req = self
+            .http
+            .patch(self.url(&format!("/api/v1/users/{}", id)))
+            .bearer_auth(token)
+            .header(header::CONTENT_TYPE, "application/merge-patch+json")
+            .body(serde_json::to_vec(patch).map_err(AppError::internal)?);
+        json(send(if_match(req, patch.version)).await?).await
+    }
+
+    /// Moves a user to the trash (admin only).
+    pub async fn delete_user(&self, token: &str, id: i32) -> Result<(), AppError> {
+        let req = self.http.delete(self.url(&format!("/api/v1/users/{}", id))).bearer_auth(token);
+        send(req).await.map(drop)
+    }
+
+    /// Lists soft-deleted users (admin only).
+    pub async fn list_deleted_users(&self, token: &str) -> Result<Vec<DeletedUser>, AppError> {
+        let req = self.http.get(self.url("/api/v1/users/trash")).bearer_auth(token);
+        json(send(req).await?).await
+    }
+
+    /// Restores a soft-deleted user (admin only).
+    pub async fn restore_user(&self, token: &str, id: i32) -> Result<PublicUser, AppError> {
+        let req = self.http.post(self.url(&format!("/api/v1/users/{}/restore", id))).bearer_auth(token);
+        json(send(req).await?).await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+}
+
+fn if_match(req: RequestBuilder, version: Option<i32>) -> RequestBuilder {
+    match version {
+        Some(v) => req.header(header::IF_MATCH, format!("\"{}\"", v)),
+        None => req,
+    }
+}
+
+/// Sends the request and turns non-2xx responses into an `AppError`.
+async fn send(req: RequestBuilder) -> Result<Response, AppError> {
+    let resp = req
+        .send()
+        .await
+        .map_err(|e| AppError::internal(format!("Server unreachable: {}", e)))?;
+
+    if resp.status().is_success() {
+        return Ok(resp);
+    }
+
+    let status = StatusCode::from_u16(resp.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let text = resp.text().await.unwrap_or_default();
+
+    // Handlers answer either with an `AppError` JSON body or plain text
+    Err(match serde_json::from_str::<ErrorBody>(&text) {
+        Ok(body) => AppError::remote(status, body.code, body.message, body.current),
+        Err(_) => match status {
+            StatusCode::UNAUTHORIZED => AppError::unauthorized(text),
+            StatusCode::FORBIDDEN => AppError::forbidden(text),
+            StatusCode::BAD_REQUEST => AppError::bad_request(text),
+            _ => AppError::new(status, "REMOTE_ERROR", text),
+        },
+    })
+}
+
+// Parses one Server-Sent Events frame; `None` for events this client does not know.
+fn parse_frame(frame: &str) -> Option<FeedItem> {
+    let (mut id, mut event, mut data) = (None, None, Vec::new());
+    for line in frame.lines() {
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "id" => id = value.parse::<u64>().ok(),
+            "event" => event = Some(value),
+            "data" => data.push(value),
+            _ => {}
+        }
+    }
+
+    match (event, id) {
+        (Some("reset"), _) => Some(FeedItem::Reset),
+        (Some(name), Some(id)) => {
+            let user = serde_json::from_str(&data.join("\n")).ok()?;
+            Some(FeedItem::Event(SequencedEvent { id, event: UserEvent::from_name(name, user)? }))
+        }
+        (None, _) if data.is_empty() => Some(FeedItem::KeepAlive),
+        _ => None,
+    }
+}
+
+async fn json<T: DeserializeOwned>(resp: Response) -> Result<T, AppError> {
+    resp.json()
+        .await
+        .map_err(|e| AppError::internal(format!("Invalid response from server: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::ResponseError;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    // Answers one request with the given status line, content type and body; returns the base URL.
+    fn serve_once(status: &'static str, content_type: &'static str, body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            reader.read_exact(&mut vec![0; content_length]).unwrap();
+
+            let response = format!(
+                "HTTP/1.1 {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                content_type,
+                body.len(),
+                body
+            );
+            reader.into_inner().write_all(response.as_bytes()).unwrap();
+        });
+        format!("http://{}", addr)
+    }
+
+    fn update() -> UpdateUser {
+        UpdateUser { id: 7, name: "Ada".into(), email: "ada@example.com".into(), role: "manager".into(), version: Some(3) }
+    }
+
+    #[actix_web::test]
+    async fn conflict_carries_the_current_user() {
+        let base = serve_once(
+            "412 Precondition Failed",
+            "application/json",
+            r#"{"code":"CONFLICT","message":"User was modified by someone else","current":{"id":7,"name":"Ada L.","email":"ada@example.com","role":"admin","version":4}}"#,
+        );
+
+        let err = ApiClient::new(base).update_user("token", &update()).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(err.code, "CONFLICT");
+        let current = err.current.unwrap();
+        assert_eq!((current.name.as_str(), current.version), ("Ada L.", 4));
+    }
+
+    #[actix_web::test]
+    async fn taken_email_keeps_the_server_code() {
+        let base = serve_once(
+            "409 Conflict",
+            "application/json",
+            r#"{"code":"EMAIL_TAKEN","message":"A user with this email already exists"}"#,
+        );
+
+        let err = ApiClient::new(base).update_user("token", &update()).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::CONFLICT);
+        assert_eq!(err.code, "EMAIL_TAKEN");
+        assert_eq!(err.message, "A user with this email already exists");
+        assert!(err.current.is_none());
+    }
+
+    #[actix_web::test]
+    async fn plain_text_unauthorized_maps_to_unauthorized() {
+        let base = serve_once("401 Unauthorized", "text/plain", "Token expired");
+
+        let err = ApiClient::new(base).get_user("token", 7).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::UNAUTHORIZED);
+        assert_eq!(err.code, "UNAUTHORIZED");
+        assert_eq!(err.message, "Token expired");
+    }
+
+    #[actix_web::test]
+    async fn other_plain_text_errors_keep_their_status() {
+        let base = serve_once("503 Service Unavailable", "text/plain", "Database unavailable");
+
+        let err = ApiClient::new(base).get_user("token", 7).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(err.code, "REMOTE_ERROR");
+    }
+
+    #[actix_web::test]
+    async fn unreachable_server_is_an_internal_error() {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+        let err = ApiClient::new(format!("http://{}", addr)).get_user("token", 7).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(err.message.starts_with("Server unreachable"));
+    }
+
+    #[test]
+    fn event_frame_is_parsed() {
+        let frame = "id: 42\nevent: user-updated\ndata: {\"id\":7,\"name\":\"Ada\",\"email\":\"ada@example.com\",\"role\":\"manager\",\"version\":4}\n\n";
+        let Some(FeedItem::Event(SequencedEvent { id, event })) = parse_frame(frame) else {
+            panic!("not an event");
+        };
+        assert_eq!(id, 42);
+        assert_eq!(event.name(), "user-updated");
+        assert_eq!(event.user().email, "ada@example.com");
+    }
+
+    #[test]
+    fn reset_and_keep_alive_frames_are_recognized() {
+        assert!(matches!(parse_frame("event: reset\ndata: {}\n\n"), Some(FeedItem::Reset)));
+        assert!(matches!(parse_frame(": keep-alive\n\n"), Some(FeedItem::KeepAlive)));
+    }
+
+    #[test]
+    fn unknown_or_broken_frames_are_skipped() {
+        assert!(parse_frame("id: 1\nevent: user-renamed\ndata: {}\n\n").is_none());
+        assert!(parse_frame("id: 1\nevent: user-created\ndata: not json\n\n").is_none());
+    }
+}
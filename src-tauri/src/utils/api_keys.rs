@@ -0,0 +1,260 @@
+use actix_web::http::Method;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::NaiveDateTime;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::MySqlPool;
+
+use crate::utils::auth::Claims;
+use crate::utils::models::ApiKey;
+
+/// Every key starts with this, so leaked keys are easy to spot and scan for.
+pub const KEY_PREFIX: &str = "mta_";
+
+/// Header accepted as an alternative to `Authorization: ApiKey <key>`.
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+/// Scopes a key can be limited to.
+pub const API_KEY_SCOPES: [&str; 3] = ["users:read", "users:write", "webhooks:manage"];
+
+/// Length of the stored, public part of a key: `mta_` plus 8 hex characters.
+const PREFIX_LEN: usize = KEY_PREFIX.len() + 8;
+
+/// Prefix of the `sub` claim for requests authenticated by an API key.
+pub const API_KEY_SUBJECT_PREFIX: &str = "apikey:";
+
+/// The API key a request was authenticated with, attached to the request like `Claims`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyIdentity {
+    pub id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub role: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl ApiKeyIdentity {
+    /// Claims for the handlers; the subject is `apikey:<prefix>`, so it never matches a user's email.
+    pub fn claims(&self) -> Claims {
+        Claims {
+            sub: format!("{}{}", API_KEY_SUBJECT_PREFIX, self.prefix),
+            uid: None,
+            role: self.role.clone(),
+            exp: self.expires_at.map_or(0, |at| at.and_utc().timestamp().max(0) as usize),
+        }
+    }
+
+    /// Whether the key's scopes cover a request. Managing API keys is never allowed,
+    /// so a leaked key cannot mint new ones.
+    pub fn allows(&self, method: &Method, path: &str) -> bool {
+        required_scope(method, path).is_some_and(|scope| self.scopes.iter().any(|s| s == scope))
+    }
+}
+
+// The scope a route needs, or `None` for routes closed to API keys.
+fn required_scope(method: &Method, path: &str) -> Option<&'static str> {
+    if path.starts_with("/api/keys") {
+        None
+    } else if path.starts_with("/api/webhooks") {
+        Some("webhooks:manage")
+    } else if method == Method::GET || method == Method::HEAD {
+        Some("users:read")
+    } else {
+        Some("users:write")
+    }
+}
+
+// High-entropy keys need no slow hash; SHA-256 keeps lookups cheap on every request.
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+struct ApiKeyRow {
+    id: i32,
+    name: String,
+    prefix: String,
+    role: String,
+    scopes: String,
+    created_by: String,
+    created_at: NaiveDateTime,
+    expires_at: Option<NaiveDateTime>,
+    last_used_at: Option<NaiveDateTime>,
+    revoked_at: Option<NaiveDateTime>,
+}
+
+impl From<ApiKeyRow> for ApiKey {
+    fn from(row: ApiKeyRow) -> Self {
+        ApiKey {
+            id: row.id,
+            name: row.name,
+            prefix: row.prefix,
+            role: row.role,
+            scopes: row.scopes.split(',').map(str::to_string).collect(),
+            created_by: row.created_by,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            last_used_at: row.last_used_at,
+            revoked_at: row.revoked_at,
+        }
+    }
+}
+
+/// Creates a key and returns it along with the key in plain text, which is not stored.
+/// Keys look like `mta_<8 hex>_<43 base64url>`; the first part is the public prefix.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn create_api_key(
+    db: &MySqlPool,
+    name: &str,
+    role: &str,
+    scopes: &[String],
+    expires_at: Option<NaiveDateTime>,
+    created_by: &str,
+) -> Result<(ApiKey, String), sqlx::Error> {
+    let mut id_bytes = [0u8; 4];
+    let mut secret_bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut id_bytes);
+    rand::thread_rng().fill_bytes(&mut secret_bytes);
+
+    let prefix = format!("{}{}", KEY_PREFIX, hex::encode(id_bytes));
+    let key = format!("{}_{}", prefix, URL_SAFE_NO_PAD.encode(secret_bytes));
+
+    let id = sqlx::query!(
+        "INSERT INTO api_keys (name, prefix, key_hash, role, scopes, created_by, expires_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        name,
+        prefix,
+        hash_key(&key),
+        role,
+        scopes.join(","),
+        created_by,
+        expires_at
+    )
+    .execute(db)
+    .await?
+    .last_insert_id();
+
+    let row = sqlx::query_as!(
+        ApiKeyRow,
+        "SELECT id, name, prefix, role, scopes, created_by, created_at, expires_at, last_used_at, revoked_at FROM api_keys WHERE id = ?",
+        id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok((row.into(), key))
+}
+
+/// Lists all keys, revoked and expired ones included, newest first.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn list_api_keys(db: &MySqlPool) -> Result<Vec<ApiKey>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        ApiKeyRow,
+        "SELECT id, name, prefix, role, scopes, created_by, created_at, expires_at, last_used_at, revoked_at FROM api_keys ORDER BY id DESC"
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows.into_iter().map(ApiKey::from).collect())
+}
+
+/// Revokes a key; it stays listed for reference. Returns `false` if no active key has the id.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn revoke_api_key(db: &MySqlPool, id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "UPDATE api_keys SET revoked_at = UTC_TIMESTAMP() WHERE id = ? AND revoked_at IS NULL",
+        id
+    )
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Looks up an unexpired, unrevoked key and records that it was used.
+/// Returns `None` for unknown, malformed, expired or revoked keys.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn authenticate(db: &MySqlPool, key: &str) -> Result<Option<ApiKeyIdentity>, sqlx::Error> {
+    let Some(prefix) = key.get(..PREFIX_LEN).filter(|p| p.starts_with(KEY_PREFIX)) else {
+        return Ok(None);
+    };
+
+    let row = sqlx::query!(
+        "SELECT id, name, key_hash, role, scopes, expires_at FROM api_keys WHERE prefix = ? AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > UTC_TIMESTAMP())",
+        prefix
+    )
+    .fetch_optional(db)
+    .await?;
+    let Some(row) = row.filter(|row| row.key_hash == hash_key(key)) else {
+        return Ok(None);
+    };
+
+    // Written at most once a minute per key, so busy scripts do not turn every read into a write
+    sqlx::query!(
+        "UPDATE api_keys SET last_used_at = UTC_TIMESTAMP() WHERE id = ? AND (last_used_at IS NULL OR last_used_at < UTC_TIMESTAMP() - INTERVAL 1 MINUTE)",
+        row.id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(Some(ApiKeyIdentity {
+        id: row.id,
+        name: row.name,
+        prefix: prefix.to_string(),
+        role: row.role,
+        scopes: row.scopes.split(',').map(str::to_string).collect(),
+        expires_at: row.expires_at,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utoipa::OpenApi;
+
+    use crate::utils::openapi::ApiDoc;
+
+    fn key(scopes: &[&str]) -> ApiKeyIdentity {
+        ApiKeyIdentity {
+            id: 1,
+            name: "ci".to_string(),
+            prefix: "mta_0a1b2c3d".to_string(),
+            role: "admin".to_string(),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn scopes_follow_method_and_path() {
+        assert_eq!(required_scope(&Method::GET, "/api/v1/users/7"), Some("users:read"));
+        assert_eq!(required_scope(&Method::PATCH, "/api/v1/users/7"), Some("users:write"));
+        assert_eq!(required_scope(&Method::POST, "/api/v1/users/7/restore"), Some("users:write"));
+        assert_eq!(required_scope(&Method::GET, "/api/webhooks"), Some("webhooks:manage"));
+        assert_eq!(required_scope(&Method::GET, "/api/keys"), None);
+    }
+
+    #[test]
+    fn keys_only_reach_routes_their_scopes_cover() {
+        let reader = key(&["users:read"]);
+        assert!(reader.allows(&Method::GET, "/api/v1/users"));
+        assert!(!reader.allows(&Method::DELETE, "/api/v1/users/7"));
+        assert!(!reader.allows(&Method::GET, "/api/webhooks"));
+        assert!(!key(&API_KEY_SCOPES).allows(&Method::POST, "/api/keys"));
+    }
+
+    // The docs must offer `api_key` on exactly the operations the middleware lets keys through.
+    #[test]
+    fn documented_api_key_security_matches_enforced_scopes() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        for (path, item) in doc["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                let method = Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+                let documented = operation["security"]
+                    .as_array()
+                    .is_some_and(|requirements| requirements.iter().any(|r| r.get("api_key").is_some()));
+                let accepted = path.starts_with("/api/") && required_scope(&method, path).is_some();
+                assert_eq!(documented, accepted, "{} {}", method, path);
+            }
+        }
+    }
+}
+
@@ -0,0 +1,163 @@
+use std::borrow::Cow;
+use std::fmt::Display;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::utils::models::PublicUser;
+use crate::utils::telemetry::current_request_id;
+
+/// Machine-readable error shared by the Actix handlers and the Tauri commands.
+///
+/// Serializes as `{ "code": "...", "message": "..." }` so the frontend can
+/// branch on `code` and still show `message` to the user.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AppError {
+    #[schema(value_type = String)]
+    pub code: Cow<'static, str>,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<PublicUser>, // latest state of the resource on a conflict
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>, // set on HTTP responses, matches `X-Request-Id`
+    #[serde(skip)]
+    status: StatusCode,
+}
+
+impl AppError {
+    /// Constructs a new `AppError` with the given HTTP status, code and message.
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        AppError {
+            code: Cow::Borrowed(code),
+            message: message.into(),
+            current: None,
+            request_id: None,
+            status,
+        }
+    }
+
+    /// The requested user does not exist.
+    pub fn user_not_found() -> Self {
+        Self::new(StatusCode::NOT_FOUND, "USER_NOT_FOUND", "User not found")
+    }
+
+    /// The update was based on a stale version; carries the current state.
+    pub fn conflict(current: PublicUser) -> Self {
+        AppError {
+            current: Some(current),
+            ..Self::new(
+                StatusCode::PRECONDITION_FAILED,
+                "CONFLICT",
+                "User was modified by someone else; reload and try again",
+            )
+        }
+    }
+
+    /// The update did not say which version it was based on.
+    pub fn precondition_required(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::PRECONDITION_REQUIRED, "PRECONDITION_REQUIRED", message)
+    }
+
+    /// The `If-Match` header can never match, e.g. because it holds a weak ETag.
+    pub fn precondition_failed(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::PRECONDITION_FAILED, "PRECONDITION_FAILED", message)
+    }
+
+    /// An admin tried to delete their own account.
+    pub fn self_delete() -> Self {
+        Self::new(StatusCode::CONFLICT, "SELF_DELETE", "You cannot delete your own account")
+    }
+
+    /// An admin tried to remove their own admin role.
+    pub fn self_demote() -> Self {
+        Self::new(StatusCode::CONFLICT, "SELF_DEMOTE", "You cannot remove your own admin role")
+    }
+
+    /// Another user already has the email.
+    pub fn email_taken() -> Self {
+        Self::new(StatusCode::CONFLICT, "EMAIL_TAKEN", "A user with this email already exists")
+    }
+
+    /// A soft-deleted user still holds the email until it is purged.
+    pub fn email_deleted() -> Self {
+        Self::new(
+            StatusCode::CONFLICT,
+            "EMAIL_DELETED",
+            "This email belongs to a deleted user; restore that user instead",
+        )
+    }
+
+    /// The change would leave the system without any administrator.
+    pub fn last_admin() -> Self {
+        Self::new(StatusCode::CONFLICT, "LAST_ADMIN", "At least one admin must remain")
+    }
+
+    /// The caller's token is missing, invalid or expired.
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", message)
+    }
+
+    /// The caller is authenticated but lacks the required role.
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "FORBIDDEN", message)
+    }
+
+    /// The request payload failed validation.
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "BAD_REQUEST", message)
+    }
+
+    /// Single sign-on was requested but no identity provider is configured.
+    pub fn sso_not_configured() -> Self {
+        Self::new(StatusCode::NOT_FOUND, "SSO_NOT_CONFIGURED", "Single sign-on is not configured")
+    }
+
+    /// Rebuilds an error received from a remote server.
+    pub fn remote(status: StatusCode, code: String, message: String, current: Option<PublicUser>) -> Self {
+        AppError {
+            code: Cow::Owned(code),
+            message,
+            current,
+            request_id: None,
+            status,
+        }
+    }
+
+    /// An unexpected failure, usually from the database.
+    pub fn internal(err: impl Display) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR", err.to_string())
+    }
+}
+
+// Implementing the `Display` trait so the error can be logged directly.
+impl Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+// Implementing the `ResponseError` trait to render the error as a JSON body.
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if self.status.is_server_error() {
+            tracing::error!(code = %self.code, message = %self.message, "request failed");
+        }
+
+        let mut body = self.clone();
+        body.request_id = current_request_id();
+        HttpResponse::build(self.status).json(body)
+    }
+}
+
+// The driver's message can contain row values, so it is logged rather than returned.
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        tracing::error!(error = %err, "Database error");
+        AppError::internal("Database error")
+    }
+}
@@ -3,24 +3,41 @@ use serde::{Deserialize, Serialize};
 use std::env;
 use chrono::{Duration, Utc};
 use actix_web::{error::ErrorUnauthorized, Error};
+use bcrypt::{hash, verify, BcryptError, DEFAULT_COST};
+use jsonwebtoken::errors::ErrorKind;
+use utoipa::ToSchema;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
 
+use crate::utils::metrics::{record_jwt_failure, time_bcrypt};
 use crate::utils::models::User;
 
 /// Struct to represent the claims embedded in the JWT.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Claims {
     pub sub: String,  // email
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<i32>, // user id; `None` for API keys and services
     pub role: String, // role (admin or user)
     pub exp: usize,   // expiration timestamp
 }
 
+/// How long an issued token (and a desktop session) stays valid.
+pub const TOKEN_TTL_HOURS: i64 = 1;
+
+/// Whether a JWT signing key is configured, for the readiness probe.
+pub fn signing_key_available() -> bool {
+    env::var("JWT_SECRET").is_ok_and(|key| !key.is_empty())
+}
+
 /// Creates a JWT token for the provided user.
 pub fn create_jwt(user: &User) -> Result<String, Error> {
     // Set expiration time (1 hour from now)
     let claims = Claims {
         sub: user.email.clone(),
+        uid: Some(user.id),
         role: user.role.clone(),
-        exp: (Utc::now() + Duration::hours(1)).timestamp() as usize,
+        exp: (Utc::now() + Duration::hours(TOKEN_TTL_HOURS)).timestamp() as usize,
     };
 
     // Get the secret key from the environment variable
@@ -35,7 +52,10 @@ pub fn create_jwt(user: &User) -> Result<String, Error> {
 /// Validates the provided JWT and returns the claims if valid.
 pub fn validate_jwt(token: &str) -> Result<Claims, Error> {
     // Get the secret key from the environment variable
-    let key = env::var("JWT_SECRET").map_err(|_| ErrorUnauthorized("JWT_SECRET not set"))?;
+    let key = env::var("JWT_SECRET").map_err(|_| {
+        record_jwt_failure("no_secret");
+        ErrorUnauthorized("JWT_SECRET not set")
+    })?;
     let decoding_key = DecodingKey::from_secret(key.as_bytes());
 
     // Configure validation settings for token
@@ -43,9 +63,41 @@ pub fn validate_jwt(token: &str) -> Result<Claims, Error> {
     validation.validate_exp = true; // Ensure the token is not expired
 
     // Decode the JWT and validate it
-    let token_data = decode::<Claims>(token, &decoding_key, &validation)
-        .map_err(|e| ErrorUnauthorized(format!("Token validation error: {}", e)))?;
+    let token_data = decode::<Claims>(token, &decoding_key, &validation).map_err(|e| {
+        record_jwt_failure(match e.kind() {
+            ErrorKind::ExpiredSignature => "expired",
+            ErrorKind::InvalidSignature => "bad_signature",
+            ErrorKind::InvalidToken | ErrorKind::Base64(_) | ErrorKind::Json(_) | ErrorKind::Utf8(_) => "malformed",
+            _ => "invalid",
+        });
+        ErrorUnauthorized(format!("Token validation error: {}", e))
+    })?;
 
     // Return the claims from the token if it's valid
     Ok(token_data.claims)
 }
+
+/// Hashes a plain-text password with bcrypt for storage.
+#[tracing::instrument(name = "bcrypt.hash", skip_all)]
+pub fn hash_password(password: &str) -> Result<String, BcryptError> {
+    time_bcrypt("hash", || hash(password, DEFAULT_COST))
+}
+
+/// Hash of a random password nobody knows, for accounts that sign in through
+/// an identity provider or a directory instead.
+pub fn unusable_password_hash() -> Result<String, BcryptError> {
+    hash_password(&random_token(32))
+}
+
+/// Checks a plain-text password against a stored bcrypt hash.
+#[tracing::instrument(name = "bcrypt.verify", skip_all)]
+pub fn verify_password(password: &str, hashed: &str) -> Result<bool, BcryptError> {
+    time_bcrypt("verify", || verify(password, hashed))
+}
+
+/// A URL-safe random string made from `len` random bytes.
+pub fn random_token(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
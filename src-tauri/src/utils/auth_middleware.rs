@@ -2,28 +2,86 @@ use actix_web::{
     body::MessageBody,
     dev::{ServiceRequest, ServiceResponse},
     http::header::AUTHORIZATION,
-    Error, HttpMessage,
+    web, Error, HttpMessage,
 };
 use actix_web::middleware::Next;
+use sqlx::MySqlPool;
 
-use crate::utils::{auth::validate_jwt, api_response::ApiResponse};
+use crate::utils::{
+    api_keys::{self, API_KEY_HEADER},
+    auth::validate_jwt,
+    api_response::ApiResponse,
+    metrics::record_jwt_failure,
+    mtls::service_identity,
+};
 
 /// Middleware that checks for a valid JWT token in the Authorization header.
 /// If the token is valid, it attaches the claims to the request.
+/// API keys are accepted as `Authorization: ApiKey <key>` or in `X-API-Key`; they attach an
+/// `ApiKeyIdentity` and are limited to the routes their scopes cover.
+/// Without either header, a client certificate mapped to a service account is accepted instead;
+/// it attaches the `ServiceIdentity` along with claims carrying the service's role.
+#[tracing::instrument(skip_all)]
 pub async fn check_auth_middleware(
     req: ServiceRequest,
     next: Next<impl MessageBody>
 ) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let api_key = match req.headers().get(AUTHORIZATION) {
+        Some(h) => h
+            .to_str()
+            .ok()
+            .and_then(|v| v.trim().strip_prefix("ApiKey "))
+            .map(|key| key.trim().to_string()),
+        None => req
+            .headers()
+            .get(API_KEY_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(|key| key.trim().to_string()),
+    };
+
+    if let Some(key) = api_key {
+        let db = match req.app_data::<web::Data<MySqlPool>>() {
+            Some(db) => db.clone(),
+            None => return Err(Error::from(ApiResponse::new(500, "Database unavailable".to_string()))),
+        };
+        let identity = match api_keys::authenticate(&db, &key).await {
+            Ok(Some(identity)) => identity,
+            Ok(None) => return Err(Error::from(ApiResponse::new(401, "Invalid API key".to_string()))),
+            Err(err) => return Err(Error::from(ApiResponse::new(500, err.to_string()))),
+        };
+        if !identity.allows(req.method(), req.path()) {
+            return Err(Error::from(ApiResponse::new(403, "The API key's scopes do not allow this request".to_string())));
+        }
+
+        req.extensions_mut().insert(identity.claims());
+        req.extensions_mut().insert(identity);
+        return next.call(req).await.map_err(|err| Error::from(ApiResponse::new(500, err.to_string())));
+    }
+
+    if !req.headers().contains_key(AUTHORIZATION) {
+        match service_identity(req.request()) {
+            Ok(Some(identity)) => {
+                req.extensions_mut().insert(identity.claims());
+                req.extensions_mut().insert(identity);
+                return next.call(req).await.map_err(|err| Error::from(ApiResponse::new(500, err.to_string())));
+            }
+            Ok(None) => {}
+            Err(message) => return Err(Error::from(ApiResponse::new(403, message))),
+        }
+    }
+
     // Get the Authorization header
     let auth_header = match req.headers().get(AUTHORIZATION) {
         Some(h) => h.to_str().unwrap_or("").trim(),
         None => {
+            record_jwt_failure("missing");
             return Err(Error::from(ApiResponse::new(401, "Unauthorized".to_string())))
         },
     };
 
     // Ensure the Authorization header has the "Bearer " prefix
     if !auth_header.starts_with("Bearer ") {
+        record_jwt_failure("bad_scheme");
         return Err(Error::from(ApiResponse::new(401, "Invalid token format".to_string())));
     }
 
@@ -0,0 +1,494 @@
+use dotenvy::dotenv;
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::utils::permissions::ROLES;
+
+/// Address the Actix server binds to, unless `SERVER_ADDR` is set.
+/// Only this machine can connect; set e.g. `0.0.0.0:8080` to serve the network.
+pub const DEFAULT_SERVER_ADDR: &str = "127.0.0.1:8080";
+
+/// Where `TLS_MODE=dev` keeps its self-signed certificate, unless the paths are set.
+pub const DEV_CERT_PATH: &str = "certs/dev-cert.pem";
+pub const DEV_KEY_PATH: &str = "certs/dev-key.pem";
+
+/// Where the desktop app's REST API lives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiMode {
+    /// Start the Actix server inside the Tauri process.
+    Embedded,
+    /// Use a standalone server at the given base URL.
+    Remote(String),
+}
+
+impl ApiMode {
+    /// Reads `API_MODE` (`embedded` or `remote`) and `API_URL` from the environment.
+    /// Defaults to `Embedded`; `remote` without `API_URL` is a configuration error.
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        match env::var("API_MODE").as_deref() {
+            Ok("remote") => {
+                let url = env::var("API_URL").expect("API_URL must be set when API_MODE=remote");
+                ApiMode::Remote(url.trim_end_matches('/').to_string())
+            }
+            _ => ApiMode::Embedded,
+        }
+    }
+}
+
+/// Address for the Actix server to bind to.
+pub fn server_addr() -> String {
+    env::var("SERVER_ADDR").unwrap_or_else(|_| DEFAULT_SERVER_ADDR.to_string())
+}
+
+/// Origins the Vite dev server and the Tauri webview load the frontend from.
+pub const DEFAULT_CORS_ORIGINS: &str = "http://localhost:1420,tauri://localhost,http://tauri.localhost";
+
+/// How long browsers may cache a preflight response, unless `CORS_MAX_AGE_SECS` is set.
+pub const DEFAULT_CORS_MAX_AGE_SECS: usize = 3600;
+
+/// Cross-origin access to the REST API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// Exact origins, e.g. `https://app.example.com`; `*` allows any origin.
+    pub allowed_origins: Vec<String>,
+    /// Whether browsers may send cookies and HTTP auth along with cross-origin requests.
+    pub allow_credentials: bool,
+    pub max_age_secs: usize,
+}
+
+impl CorsConfig {
+    /// Reads `CORS_ALLOWED_ORIGINS` (comma-separated), `CORS_ALLOW_CREDENTIALS` and
+    /// `CORS_MAX_AGE_SECS` from the environment.
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let allowed_origins = env::var("CORS_ALLOWED_ORIGINS")
+            .unwrap_or_else(|_| DEFAULT_CORS_ORIGINS.to_string())
+            .split(',')
+            .map(|origin| origin.trim().trim_end_matches('/').to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+        let allow_credentials = env::var("CORS_ALLOW_CREDENTIALS").is_ok_and(|v| v == "true" || v == "1");
+        let max_age_secs = env::var("CORS_MAX_AGE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_CORS_MAX_AGE_SECS);
+
+        CorsConfig { allowed_origins, allow_credentials, max_age_secs }
+    }
+}
+
+/// HTTPS settings for the Actix server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert_path: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
+    pub key_path: PathBuf,
+    /// Generate a self-signed certificate for localhost if the files do not exist yet.
+    pub self_signed: bool,
+    /// Plain-HTTP address whose requests are redirected to HTTPS, if any.
+    pub redirect_addr: Option<String>,
+    /// PEM bundle of the CAs whose client certificates identify services, if mTLS is on.
+    pub client_ca_path: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// Reads `TLS_MODE` (`off`, `on` or `dev`), `TLS_CERT_PATH`, `TLS_KEY_PATH`,
+    /// `HTTP_REDIRECT_ADDR` and `TLS_CLIENT_CA_PATH`. Returns `None` for plain HTTP, the default.
+    /// `on` without both paths is a configuration error.
+    pub fn from_env() -> Option<Self> {
+        dotenv().ok();
+        let self_signed = match env::var("TLS_MODE").as_deref() {
+            Ok("on") => false,
+            Ok("dev") => true,
+            _ => return None,
+        };
+
+        let cert_path = env::var("TLS_CERT_PATH").map(PathBuf::from);
+        let key_path = env::var("TLS_KEY_PATH").map(PathBuf::from);
+        let (cert_path, key_path) = if self_signed {
+            (
+                cert_path.unwrap_or_else(|_| PathBuf::from(DEV_CERT_PATH)),
+                key_path.unwrap_or_else(|_| PathBuf::from(DEV_KEY_PATH)),
+            )
+        } else {
+            (
+                cert_path.expect("TLS_CERT_PATH must be set when TLS_MODE=on"),
+                key_path.expect("TLS_KEY_PATH must be set when TLS_MODE=on"),
+            )
+        };
+
+        Some(TlsConfig {
+            cert_path,
+            key_path,
+            self_signed,
+            redirect_addr: env::var("HTTP_REDIRECT_ADDR").ok().filter(|addr| !addr.is_empty()),
+            client_ca_path: env::var("TLS_CLIENT_CA_PATH").ok().filter(|path| !path.is_empty()).map(PathBuf::from),
+        })
+    }
+}
+
+/// Single sign-on through an OpenID Connect identity provider.
+#[derive(Clone, PartialEq, Eq)]
+pub struct OidcConfig {
+    /// Issuer URL; `/.well-known/openid-configuration` is read from below it.
+    pub issuer: String,
+    pub client_id: String,
+    /// Omitted for public clients, which rely on PKCE alone.
+    pub client_secret: Option<String>,
+    /// Callback of the browser flow, e.g. `https://api.example.com/auth/oidc/callback`.
+    pub redirect_url: Option<String>,
+    pub scopes: String,
+    /// ID token claim listing the user's groups.
+    pub groups_claim: String,
+    /// Identity-provider group and the role it grants.
+    pub role_mapping: Vec<(String, String)>,
+    /// Role of provisioned users that are in none of the mapped groups.
+    pub default_role: String,
+    /// Create unknown users on their first sign-on instead of rejecting them.
+    pub auto_provision: bool,
+    /// Link an unlinked local account with the same, provider-verified email on first sign-on.
+    pub link_by_email: bool,
+    /// Where the browser flow sends users after signing in; the token goes in the fragment.
+    pub post_login_url: Option<String>,
+}
+
+impl fmt::Debug for OidcConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OidcConfig")
+            .field("issuer", &self.issuer)
+            .field("client_id", &self.client_id)
+            .field("client_secret", &self.client_secret.as_ref().map(|_| "[REDACTED]"))
+            .field("redirect_url", &self.redirect_url)
+            .field("scopes", &self.scopes)
+            .field("groups_claim", &self.groups_claim)
+            .field("role_mapping", &self.role_mapping)
+            .field("default_role", &self.default_role)
+            .field("auto_provision", &self.auto_provision)
+            .field("link_by_email", &self.link_by_email)
+            .field("post_login_url", &self.post_login_url)
+            .finish()
+    }
+}
+
+impl OidcConfig {
+    /// Reads the `OIDC_*` settings. Returns `None` unless `OIDC_ISSUER` and `OIDC_CLIENT_ID`
+    /// are set; a role that does not exist is a configuration error.
+    pub fn from_env() -> Option<Self> {
+        dotenv().ok();
+        let issuer = env::var("OIDC_ISSUER").ok().filter(|v| !v.is_empty())?;
+        let client_id = env::var("OIDC_CLIENT_ID").ok().filter(|v| !v.is_empty())?;
+        let optional = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+
+        let role_mapping = parse_role_mapping("OIDC", &optional("OIDC_ROLE_MAPPING").unwrap_or_default(), ',');
+        let default_role = optional("OIDC_DEFAULT_ROLE").unwrap_or_else(|| "user".to_string());
+        check_role("OIDC", &default_role);
+
+        Some(OidcConfig {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: optional("OIDC_CLIENT_SECRET"),
+            redirect_url: optional("OIDC_REDIRECT_URL"),
+            scopes: optional("OIDC_SCOPES").unwrap_or_else(|| "openid email profile".to_string()),
+            groups_claim: optional("OIDC_GROUPS_CLAIM").unwrap_or_else(|| "groups".to_string()),
+            role_mapping,
+            default_role,
+            auto_provision: optional("OIDC_AUTO_PROVISION").is_none_or(|v| v != "false" && v != "0"),
+            link_by_email: optional("OIDC_LINK_BY_EMAIL").is_some_and(|v| v != "false" && v != "0"),
+            post_login_url: optional("OIDC_POST_LOGIN_URL"),
+        })
+    }
+
+    /// The most privileged role granted by any of the groups, if one is mapped.
+    pub fn role_for_groups(&self, groups: &[String]) -> Option<String> {
+        most_privileged_role(&self.role_mapping, |group| groups.iter().any(|g| g == group))
+    }
+}
+
+/// Password logins checked by binding to an LDAP or Active Directory server.
+#[derive(Clone, PartialEq, Eq)]
+pub struct LdapConfig {
+    /// `ldap://` or `ldaps://` URL of the directory server.
+    pub url: String,
+    /// Upgrade a plain `ldap://` connection with StartTLS.
+    pub starttls: bool,
+    /// Account that looks users up; anonymous search if unset.
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    /// Where users are searched, e.g. `ou=people,dc=example,dc=org`.
+    pub user_base_dn: String,
+    /// Search filter; `{login}` is replaced by the escaped login.
+    pub user_filter: String,
+    pub email_attribute: String,
+    pub name_attribute: String,
+    /// Attribute of the user entry listing its groups, e.g. `memberOf`.
+    pub group_attribute: String,
+    /// Also search groups here, for directories without `memberOf`.
+    pub group_base_dn: Option<String>,
+    /// Group search filter; `{dn}` is replaced by the escaped user DN.
+    pub group_filter: String,
+    /// Group (DN or CN) and the role it grants.
+    pub role_mapping: Vec<(String, String)>,
+    /// Role of provisioned users that are in none of the mapped groups.
+    pub default_role: String,
+    /// Create unknown users on their first login instead of rejecting them.
+    pub auto_provision: bool,
+    /// Link an unlinked local account with the entry's email on its first directory login.
+    pub link_by_email: bool,
+    /// Limit for connecting and for each directory operation.
+    pub timeout: Duration,
+}
+
+impl fmt::Debug for LdapConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LdapConfig")
+            .field("url", &self.url)
+            .field("starttls", &self.starttls)
+            .field("bind_dn", &self.bind_dn)
+            .field("bind_password", &self.bind_password.as_ref().map(|_| "[REDACTED]"))
+            .field("user_base_dn", &self.user_base_dn)
+            .field("user_filter", &self.user_filter)
+            .field("email_attribute", &self.email_attribute)
+            .field("name_attribute", &self.name_attribute)
+            .field("group_attribute", &self.group_attribute)
+            .field("group_base_dn", &self.group_base_dn)
+            .field("group_filter", &self.group_filter)
+            .field("role_mapping", &self.role_mapping)
+            .field("default_role", &self.default_role)
+            .field("auto_provision", &self.auto_provision)
+            .field("link_by_email", &self.link_by_email)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl LdapConfig {
+    /// Reads the `LDAP_*` settings. Returns `None` unless `LDAP_URL` is set; without
+    /// `LDAP_USER_BASE_DN` or with a role that does not exist it is a configuration error.
+    pub fn from_env() -> Option<Self> {
+        dotenv().ok();
+        let url = env::var("LDAP_URL").ok().filter(|v| !v.is_empty())?;
+        let optional = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+        let flag = |name: &str, default: bool| optional(name).map(|v| v != "false" && v != "0").unwrap_or(default);
+
+        // Group DNs contain commas, so entries are separated by semicolons
+        let role_mapping = parse_role_mapping("LDAP", &optional("LDAP_ROLE_MAPPING").unwrap_or_default(), ';');
+        let default_role = optional("LDAP_DEFAULT_ROLE").unwrap_or_else(|| "user".to_string());
+        check_role("LDAP", &default_role);
+
+        Some(LdapConfig {
+            url,
+            starttls: flag("LDAP_STARTTLS", false),
+            bind_dn: optional("LDAP_BIND_DN"),
+            bind_password: optional("LDAP_BIND_PASSWORD"),
+            user_base_dn: optional("LDAP_USER_BASE_DN").expect("LDAP_USER_BASE_DN must be set when LDAP_URL is"),
+            user_filter: optional("LDAP_USER_FILTER").unwrap_or_else(|| "(mail={login})".to_string()),
+            email_attribute: optional("LDAP_EMAIL_ATTRIBUTE").unwrap_or_else(|| "mail".to_string()),
+            name_attribute: optional("LDAP_NAME_ATTRIBUTE").unwrap_or_else(|| "cn".to_string()),
+            group_attribute: optional("LDAP_GROUP_ATTRIBUTE").unwrap_or_else(|| "memberOf".to_string()),
+            group_base_dn: optional("LDAP_GROUP_BASE_DN"),
+            group_filter: optional("LDAP_GROUP_FILTER").unwrap_or_else(|| "(|(member={dn})(uniqueMember={dn}))".to_string()),
+            role_mapping,
+            default_role,
+            auto_provision: flag("LDAP_AUTO_PROVISION", true),
+            link_by_email: flag("LDAP_LINK_BY_EMAIL", false),
+            timeout: Duration::from_secs(optional("LDAP_TIMEOUT_SECS").and_then(|v| v.parse().ok()).unwrap_or(5)),
+        })
+    }
+
+    /// The most privileged role granted by any of the group DNs, if one is mapped.
+    /// A mapping matches a group by its full DN or by its CN, ignoring case.
+    pub fn role_for_groups(&self, groups: &[String]) -> Option<String> {
+        most_privileged_role(&self.role_mapping, |group| {
+            groups.iter().any(|dn| {
+                let cn = dn
+                    .split(',')
+                    .next()
+                    .and_then(|rdn| rdn.split_once('='))
+                    .filter(|(attribute, _)| attribute.trim().eq_ignore_ascii_case("cn"))
+                    .map(|(_, value)| value.trim());
+                dn.eq_ignore_ascii_case(group) || cn.is_some_and(|cn| cn.eq_ignore_ascii_case(group))
+            })
+        })
+    }
+}
+
+// Parses `group=role` entries; a role that does not exist is a configuration error.
+fn parse_role_mapping(source: &str, value: &str, separator: char) -> Vec<(String, String)> {
+    let mapping: Vec<(String, String)> = value
+        .split(separator)
+        .filter_map(|entry| entry.rsplit_once('='))
+        .map(|(group, role)| (group.trim().to_string(), role.trim().to_string()))
+        .collect();
+    for (_, role) in &mapping {
+        check_role(source, role);
+    }
+    mapping
+}
+
+/// Reads the length of a job's interval from `name`, or `default` if it is unset.
+/// Anything but a whole number of at least 1 is a configuration error;
+/// `tokio::time::interval` panics on a zero period.
+pub fn interval_from_env(name: &str, default: u64) -> u64 {
+    dotenv().ok();
+    parse_interval(name, env::var(name).ok().filter(|v| !v.is_empty()).as_deref(), default)
+}
+
+fn parse_interval(name: &str, value: Option<&str>, default: u64) -> u64 {
+    match value.map(|v| (v, v.trim().parse::<u64>())) {
+        None => default,
+        Some((_, Ok(n))) if n > 0 => n,
+        Some((v, _)) => panic!("{} must be a whole number of at least 1, not '{}'", name, v),
+    }
+}
+
+fn check_role(source: &str, role: &str) {
+    if !ROLES.contains(&role) {
+        panic!("{} role mapping: invalid role '{}'", source, role);
+    }
+}
+
+// ROLES is ordered from most to least privileged.
+fn most_privileged_role(mapping: &[(String, String)], is_member: impl Fn(&str) -> bool) -> Option<String> {
+    ROLES
+        .iter()
+        .find(|role| mapping.iter().any(|(group, mapped)| mapped == *role && is_member(group)))
+        .map(|role| role.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oidc_config(role_mapping: &str) -> OidcConfig {
+        OidcConfig {
+            issuer: "https://idp.example.com".to_string(),
+            client_id: "app".to_string(),
+            client_secret: None,
+            redirect_url: None,
+            scopes: "openid email profile".to_string(),
+            groups_claim: "groups".to_string(),
+            role_mapping: parse_role_mapping("OIDC", role_mapping, ','),
+            default_role: "user".to_string(),
+            auto_provision: true,
+            link_by_email: false,
+            post_login_url: None,
+        }
+    }
+
+    fn groups(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn oidc_groups_grant_the_most_privileged_role() {
+        let config = oidc_config("staff=user,app-managers=manager,app-admins=admin");
+
+        assert_eq!(config.role_for_groups(&groups(&["staff", "app-managers"])), Some("manager".to_string()));
+        assert_eq!(config.role_for_groups(&groups(&["app-admins", "staff"])), Some("admin".to_string()));
+    }
+
+    #[test]
+    fn oidc_groups_match_exactly() {
+        let config = oidc_config("app-admins=admin");
+
+        assert_eq!(config.role_for_groups(&groups(&["App-Admins", "app-admins-old"])), None);
+        assert_eq!(config.role_for_groups(&[]), None);
+    }
+
+    fn ldap_config(role_mapping: &str) -> LdapConfig {
+        LdapConfig {
+            url: "ldap://localhost:389".to_string(),
+            starttls: false,
+            bind_dn: None,
+            bind_password: None,
+            user_base_dn: "ou=people,dc=example,dc=org".to_string(),
+            user_filter: "(mail={login})".to_string(),
+            email_attribute: "mail".to_string(),
+            name_attribute: "cn".to_string(),
+            group_attribute: "memberOf".to_string(),
+            group_base_dn: None,
+            group_filter: "(member={dn})".to_string(),
+            role_mapping: parse_role_mapping("LDAP", role_mapping, ';'),
+            default_role: "user".to_string(),
+            auto_provision: true,
+            link_by_email: false,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn ldap_role_mapping_splits_on_the_last_equals_sign() {
+        let mapping = parse_role_mapping(
+            "LDAP",
+            "cn=app-admins,ou=groups,dc=example,dc=org=admin; app-managers = manager",
+            ';',
+        );
+        assert_eq!(
+            mapping,
+            vec![
+                ("cn=app-admins,ou=groups,dc=example,dc=org".to_string(), "admin".to_string()),
+                ("app-managers".to_string(), "manager".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn ldap_role_mapping_skips_entries_without_a_role() {
+        assert!(parse_role_mapping("LDAP", "", ';').is_empty());
+        assert_eq!(parse_role_mapping("LDAP", "app-admins;staff=user;", ';').len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid role 'root'")]
+    fn ldap_role_mapping_rejects_unknown_roles() {
+        parse_role_mapping("LDAP", "app-admins=root", ';');
+    }
+
+    #[test]
+    fn ldap_groups_match_by_dn_or_cn_ignoring_case() {
+        let config = ldap_config("cn=app-admins,ou=groups,dc=example,dc=org=admin;App-Managers=manager");
+
+        let by_dn = groups(&["CN=App-Admins,OU=Groups,DC=example,DC=org"]);
+        assert_eq!(config.role_for_groups(&by_dn), Some("admin".to_string()));
+        let by_cn = groups(&["cn=app-managers,ou=teams,dc=example,dc=org"]);
+        assert_eq!(config.role_for_groups(&by_cn), Some("manager".to_string()));
+    }
+
+    #[test]
+    fn ldap_groups_grant_the_most_privileged_role() {
+        let config = ldap_config("app-managers=manager;app-admins=admin");
+        let member_of = groups(&["cn=app-managers,ou=groups,dc=example,dc=org", "cn=app-admins,ou=groups,dc=example,dc=org"]);
+
+        assert_eq!(config.role_for_groups(&member_of), Some("admin".to_string()));
+    }
+
+    #[test]
+    fn ldap_groups_do_not_match_by_prefix() {
+        let config = ldap_config("app-admins=admin");
+        let member_of = groups(&["cn=app-admins-old,ou=groups,dc=example,dc=org", "ou=app-admins,dc=example,dc=org"]);
+
+        assert_eq!(config.role_for_groups(&member_of), None);
+    }
+
+    #[test]
+    fn interval_defaults_when_unset() {
+        assert_eq!(parse_interval("USER_PURGE_INTERVAL_MINUTES", None, 60), 60);
+        assert_eq!(parse_interval("USER_PURGE_INTERVAL_MINUTES", Some(" 5 "), 60), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "USER_PURGE_INTERVAL_MINUTES must be a whole number of at least 1, not '0'")]
+    fn zero_interval_is_rejected() {
+        parse_interval("USER_PURGE_INTERVAL_MINUTES", Some("0"), 60);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 1")]
+    fn garbage_interval_is_rejected() {
+        parse_interval("USER_PURGE_INTERVAL_MINUTES", Some("hourly"), 60);
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use sqlx::MySqlPool;
+use tokio::sync::broadcast;
+
+use crate::utils::models::PublicUser;
+use crate::utils::user_repo;
+
+/// How many undelivered events a slow subscriber may fall behind before it starts losing them.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// How many past events are kept for subscribers resuming from a last-seen id.
+const HISTORY_CAPACITY: usize = 1024;
+
+/// A change to a user, published by both the Actix handlers and the Tauri commands.
+#[derive(Debug, Clone, Serialize)]
+pub enum UserEvent {
+    /// A user was created, or restored from the trash.
+    Created(PublicUser),
+    Updated(PublicUser),
+    /// A user was moved to the trash; carries the user as it was before.
+    Deleted(PublicUser),
+}
+
+impl UserEvent {
+    /// Event name used for Tauri events and change feeds.
+    pub fn name(&self) -> &'static str {
+        match self {
+            UserEvent::Created(_) => "user-created",
+            UserEvent::Updated(_) => "user-updated",
+            UserEvent::Deleted(_) => "user-deleted",
+        }
+    }
+
+    /// The event called `name` on change feeds, about `user`.
+    pub fn from_name(name: &str, user: PublicUser) -> Option<Self> {
+        match name {
+            "user-created" => Some(UserEvent::Created(user)),
+            "user-updated" => Some(UserEvent::Updated(user)),
+            "user-deleted" => Some(UserEvent::Deleted(user)),
+            _ => None,
+        }
+    }
+
+    /// The user the event is about.
+    pub fn user(&self) -> &PublicUser {
+        match self {
+            UserEvent::Created(u) | UserEvent::Updated(u) | UserEvent::Deleted(u) => u,
+        }
+    }
+}
+
+/// A published event with its position in the feed.
+#[derive(Debug, Clone)]
+pub struct SequencedEvent {
+    pub id: u64,
+    pub event: UserEvent,
+}
+
+/// What a resuming subscriber missed since its last-seen id.
+pub enum Backlog {
+    /// The missed events, oldest first; empty if it is up to date.
+    Events(Vec<SequencedEvent>),
+    /// The missed events are no longer kept; the subscriber has to reload its state.
+    Gap,
+}
+
+struct History {
+    next_id: u64,
+    events: VecDeque<SequencedEvent>,
+}
+
+/// In-process fan-out of user changes to every subscriber.
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<SequencedEvent>,
+    history: Arc<Mutex<History>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+        // Ids start at the startup time in milliseconds, so they keep increasing across restarts
+        // and an id from before a restart is never mistaken for one issued after it.
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        EventBus {
+            tx,
+            history: Arc::new(Mutex::new(History {
+                next_id: start + 1,
+                events: VecDeque::with_capacity(HISTORY_CAPACITY),
+            })),
+        }
+    }
+
+    /// Assigns the next id to the event, records it and publishes it.
+    pub fn publish(&self, event: UserEvent) {
+        let mut history = self.history.lock().unwrap();
+        let sequenced = SequencedEvent { id: history.next_id, event };
+        history.next_id += 1;
+
+        if history.events.len() == HISTORY_CAPACITY {
+            history.events.pop_front();
+        }
+        history.events.push_back(sequenced.clone());
+
+        // Sent under the lock so subscribers see events in id order
+        let _ = self.tx.send(sequenced);
+    }
+
+    /// Looks up a freshly inserted user and publishes `user-created` for it.
+    /// A failed lookup only means the event is skipped; the insert itself already succeeded.
+    pub async fn publish_created(&self, db: &MySqlPool, id: u64) {
+        if let Ok(Some(user)) = user_repo::find_public_user(db, id as i32).await {
+            self.publish(UserEvent::Created(user));
+        }
+    }
+
+    /// Subscribes to events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<SequencedEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Subscribes to new events and returns the ones published after `last_id`.
+    /// Nothing is lost or repeated between the backlog and the receiver.
+    pub fn resume(&self, last_id: u64) -> (Backlog, broadcast::Receiver<SequencedEvent>) {
+        let history = self.history.lock().unwrap();
+        let rx = self.tx.subscribe();
+
+        let oldest = history.events.front().map_or(history.next_id, |e| e.id);
+        // `last_id` comes from the client, so it may be anything up to u64::MAX
+        let backlog = if last_id.saturating_add(1) < oldest || last_id >= history.next_id {
+            // Either too old to replay, or from a feed this server never issued
+            Backlog::Gap
+        } else {
+            Backlog::Events(history.events.iter().filter(|e| e.id > last_id).cloned().collect())
+        };
+
+        (backlog, rx)
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: i32) -> PublicUser {
+        PublicUser {
+            id,
+            name: format!("User {}", id),
+            email: format!("user{}@example.com", id),
+            role: "user".to_string(),
+            version: 1,
+        }
+    }
+
+    fn next_id(bus: &EventBus) -> u64 {
+        bus.history.lock().unwrap().next_id
+    }
+
+    fn ids(backlog: Backlog) -> Option<Vec<u64>> {
+        match backlog {
+            Backlog::Events(events) => Some(events.iter().map(|e| e.id).collect()),
+            Backlog::Gap => None,
+        }
+    }
+
+    #[test]
+    fn resume_with_empty_history() {
+        let bus = EventBus::new();
+        let last = next_id(&bus) - 1;
+
+        assert_eq!(ids(bus.resume(last).0), Some(vec![]));
+        // Anything older predates this server's feed
+        assert_eq!(ids(bus.resume(last - 1).0), None);
+    }
+
+    #[test]
+    fn resume_replays_missed_events() {
+        let bus = EventBus::new();
+        let first = next_id(&bus);
+        for i in 0..3 {
+            bus.publish(UserEvent::Created(user(i)));
+        }
+
+        assert_eq!(ids(bus.resume(first).0), Some(vec![first + 1, first + 2]));
+        assert_eq!(ids(bus.resume(first - 1).0), Some(vec![first, first + 1, first + 2]));
+    }
+
+    #[test]
+    fn resume_at_exact_tail_is_up_to_date() {
+        let bus = EventBus::new();
+        bus.publish(UserEvent::Created(user(1)));
+        let last = next_id(&bus) - 1;
+
+        assert_eq!(ids(bus.resume(last).0), Some(vec![]));
+    }
+
+    #[test]
+    fn resume_past_history_is_a_gap() {
+        let bus = EventBus::new();
+        let first = next_id(&bus);
+        for i in 0..HISTORY_CAPACITY as i32 + 1 {
+            bus.publish(UserEvent::Updated(user(i)));
+        }
+
+        // The first event has been dropped from the history
+        assert_eq!(ids(bus.resume(first - 1).0), None);
+        assert_eq!(ids(bus.resume(first).0).map(|ids| ids.len()), Some(HISTORY_CAPACITY));
+    }
+
+    #[test]
+    fn resume_from_the_future_is_a_gap() {
+        let bus = EventBus::new();
+        bus.publish(UserEvent::Created(user(1)));
+
+        assert_eq!(ids(bus.resume(next_id(&bus)).0), None);
+        assert_eq!(ids(bus.resume(u64::MAX).0), None);
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Writes a file that only the current user can read (0600 on Unix), replacing any previous one.
+/// Used for secrets such as private keys and stored credentials.
+///
+/// The contents go to a fresh file that is then renamed over `path`: the mode only applies
+/// to files being created, so rewriting an existing file would keep looser permissions.
+pub fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+    // Left behind by an interrupted write
+    match fs::remove_file(&tmp) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::auth::random_token;
+
+    #[test]
+    fn replaces_the_contents() {
+        let dir = std::env::temp_dir().join(random_token(8));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("secret.key");
+
+        write_private(&path, b"first").unwrap();
+        write_private(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert!(!dir.join("secret.key.tmp").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tightens_the_permissions_of_an_existing_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(random_token(8));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("secret.key");
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&path, b"new").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
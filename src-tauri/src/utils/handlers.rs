@@ -1,54 +1,363 @@
-use actix_web::{web, HttpResponse, HttpRequest, Responder};
+use std::collections::VecDeque;
+use std::time::Duration;
+use actix_web::{web, HttpResponse, HttpRequest, Responder, ResponseError};
+use actix_web::http::header::{CACHE_CONTROL, ETAG, IF_MATCH, LOCATION};
+use chrono::Utc;
+use futures_util::stream;
 use sqlx::MySqlPool;
-use crate::utils::models::{User, NewUser, LoginRequest, LoginResponse, UpdateUser, PublicUser};
-use crate::utils::auth::{create_jwt, Claims};
-use bcrypt::{hash, verify, DEFAULT_COST};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{interval, Interval};
+use crate::utils::models::{NewUser, LoginRequest, LoginResponse, RefreshRequest, UpdateUser, ReplaceUser, PatchUser, PublicUser, DeletedUser, FeedQuery, NewWebhook, CreatedWebhook, Webhook, WebhookDelivery, DeliveryAttempt, NewApiKey, CreatedApiKey, ApiKey, OidcCallbackQuery, OidcStartRequest, OidcStartResponse, OidcExchangeRequest};
+use crate::utils::auth::{create_jwt, hash_password, Claims};
+use crate::utils::metrics::record_login;
+use crate::utils::app_error::AppError;
+use crate::utils::events::{Backlog, EventBus, SequencedEvent, UserEvent};
+use crate::utils::user_repo::{self, UpdateOutcome};
+use crate::utils::refresh_tokens;
+use crate::utils::permissions::{authorize_patch, can_view_user_changes, REGISTERED_ROLE, ROLES};
+use crate::utils::validation::{validate_patch, validate_role};
+use crate::utils::webhooks::{self, WEBHOOK_EVENTS};
+use crate::utils::api_keys::{self, API_KEY_SCOPES};
+use crate::utils::oidc::{self, AuthRequest, OidcClient, PendingLogins};
+use crate::utils::ldap;
+use crate::utils::config::LdapConfig;
 
 /// Register a new user.
-pub async fn register(db: web::Data<MySqlPool>, form: web::Json<NewUser>) -> impl Responder {
+#[utoipa::path(
+    post,
+    path = "/auth/register",
+    tag = "auth",
+    request_body = NewUser,
+    responses(
+        (status = 201, description = "User registered with the `user` role; any `role` in the body is ignored"),
+        (status = 409, description = "Email taken, possibly by a deleted user", body = AppError),
+        (status = 500, description = "Failed to register user"),
+    )
+)]
+pub async fn register(
+    db: web::Data<MySqlPool>,
+    events: web::Data<EventBus>,
+    form: web::Json<NewUser>,
+) -> impl Responder {
+    // Anyone can register, so the caller does not get to pick a role
+    let user = NewUser { role: REGISTERED_ROLE.to_string(), ..form.into_inner() };
+
     // Hash the user's password before storing it
-    let hashed = hash(&form.password, DEFAULT_COST).unwrap();
+    let hashed = match hash_password(&user.password) {
+        Ok(h) => h,
+        Err(_) => return HttpResponse::InternalServerError().body("Failed to register user"),
+    };
 
-    let result = sqlx::query!(
-        "INSERT INTO users (name, email, password, role) VALUES (?, ?, ?, ?)",
-        form.name,
-        form.email,
-        hashed,
-        form.role
-    )
-    .execute(db.get_ref())
-    .await;
+    let result = user_repo::insert_user(db.get_ref(), &user, &hashed).await;
 
     match result {
-        Ok(_) => HttpResponse::Created().body("User registered"),
+        Ok(id) => {
+            events.publish_created(db.get_ref(), id).await;
+            HttpResponse::Created().body("User registered")
+        }
+        Err(err) if err.status_code().is_client_error() => err.error_response(),
         Err(_) => HttpResponse::InternalServerError().body("Failed to register user"),
     }
 }
 
 /// User login to authenticate and get JWT token.
-pub async fn login(db: web::Data<MySqlPool>, form: web::Json<LoginRequest>) -> impl Responder {
-    // Fetch the user from the database
-    let user = sqlx::query_as!(
-        User,
-        "SELECT id, name, email, role, password FROM users WHERE email = ?",
-        form.email
-    )
-    .fetch_optional(db.get_ref())
-    .await
-    .unwrap();
+/// With `LDAP_URL` set, users in the directory log in with their directory password.
+#[utoipa::path(
+    post,
+    path = "/auth/login",
+    tag = "auth",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Logged in", body = LoginResponse),
+        (status = 401, description = "Invalid credentials"),
+        (status = 403, description = "No account for this directory user"),
+    )
+)]
+pub async fn login(
+    db: web::Data<MySqlPool>,
+    events: web::Data<EventBus>,
+    ldap: Option<web::Data<LdapConfig>>,
+    form: web::Json<LoginRequest>,
+) -> impl Responder {
+    let user = match ldap::password_login(db.get_ref(), ldap.as_deref(), &form.email, &form.password).await {
+        Ok(Some((u, created))) => {
+            if created {
+                events.publish_created(db.get_ref(), u.id as u64).await;
+            }
+            Some(u)
+        }
+        Ok(None) => None,
+        Err(err) => {
+            record_login(false);
+            return err.error_response();
+        }
+    };
+    record_login(user.is_some());
 
     match user {
-        Some(u) if verify(&form.password, &u.password).unwrap() => {
+        Some(u) => {
+            // Hand out a refresh token only when "remember me" was ticked
+            let refresh_token = if form.remember_me {
+                match refresh_tokens::issue(db.get_ref(), u.id).await {
+                    Ok(t) => Some(t),
+                    Err(_) => return HttpResponse::InternalServerError().body("Failed to issue refresh token"),
+                }
+            } else {
+                None
+            };
+
             match create_jwt(&u) {
-                Ok(token) => HttpResponse::Ok().json(LoginResponse { token, name: u.name, role: u.role }),
+                Ok(token) => HttpResponse::Ok().json(LoginResponse { token, name: u.name, email: u.email, role: u.role, refresh_token }),
                 Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
             }
         }
-        _ => HttpResponse::Unauthorized().body("Invalid credentials"),
+        None => HttpResponse::Unauthorized().body("Invalid credentials"),
+    }
+}
+
+/// Exchange a refresh token for a new JWT and a rotated refresh token.
+#[utoipa::path(
+    post,
+    path = "/auth/refresh",
+    tag = "auth",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "New token and rotated refresh token", body = LoginResponse),
+        (status = 401, description = "Invalid or expired refresh token"),
+    )
+)]
+pub async fn refresh(db: web::Data<MySqlPool>, form: web::Json<RefreshRequest>) -> impl Responder {
+    match refresh_tokens::redeem(db.get_ref(), &form.refresh_token).await {
+        Ok(Some((u, refresh_token))) => match create_jwt(&u) {
+            Ok(token) => HttpResponse::Ok().json(LoginResponse { token, name: u.name, email: u.email, role: u.role, refresh_token: Some(refresh_token) }),
+            Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+        },
+        Ok(None) => HttpResponse::Unauthorized().body("Invalid or expired refresh token"),
+        Err(_) => HttpResponse::InternalServerError().body("Failed to refresh session"),
     }
 }
 
+/// Revoke a refresh token so it can no longer restore a session.
+#[utoipa::path(
+    post,
+    path = "/auth/logout",
+    tag = "auth",
+    request_body = RefreshRequest,
+    responses(
+        (status = 204, description = "Refresh token revoked"),
+    )
+)]
+pub async fn logout(db: web::Data<MySqlPool>, form: web::Json<RefreshRequest>) -> impl Responder {
+    match refresh_tokens::revoke(db.get_ref(), &form.refresh_token).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(_) => HttpResponse::InternalServerError().body("Failed to log out"),
+    }
+}
+
+/// Start a single sign-on in the browser by redirecting to the identity provider.
+#[utoipa::path(
+    get,
+    path = "/auth/oidc/login",
+    tag = "auth",
+    responses(
+        (status = 302, description = "Redirect to the identity provider"),
+        (status = 404, description = "Single sign-on is not configured"),
+    )
+)]
+pub async fn oidc_login(oidc: Option<web::Data<OidcClient>>, pending: web::Data<PendingLogins>) -> impl Responder {
+    let Some(oidc) = oidc else {
+        return AppError::sso_not_configured().error_response();
+    };
+    let Some(redirect_url) = oidc.config().redirect_url.clone() else {
+        return AppError::internal("OIDC_REDIRECT_URL is not set").error_response();
+    };
+
+    match oidc.authorization_request(&redirect_url).await {
+        Ok(request) => {
+            let url = request.url.clone();
+            let cookie = oidc::state_cookie(&request.state, redirect_url.starts_with("https://"));
+            pending.insert(request);
+            HttpResponse::Found().insert_header((LOCATION, url)).cookie(cookie).finish()
+        }
+        Err(err) => err.error_response(),
+    }
+}
+
+/// Where the identity provider sends the browser back after a single sign-on.
+/// Redirects to `OIDC_POST_LOGIN_URL` with the token in the fragment if it is set, otherwise answers with JSON.
+#[utoipa::path(
+    get,
+    path = "/auth/oidc/callback",
+    tag = "auth",
+    params(
+        ("code" = Option<String>, Query, description = "Authorization code"),
+        ("state" = Option<String>, Query, description = "State of the sign-in this completes"),
+    ),
+    responses(
+        (status = 200, description = "Signed in", body = LoginResponse),
+        (status = 302, description = "Signed in; redirect to the post-login URL"),
+        (status = 400, description = "Unknown or expired sign-in, or started in another browser"),
+        (status = 401, description = "The identity provider refused the sign-in"),
+        (status = 403, description = "No account for this identity"),
+        (status = 404, description = "Single sign-on is not configured"),
+    )
+)]
+pub async fn oidc_callback(
+    db: web::Data<MySqlPool>,
+    events: web::Data<EventBus>,
+    oidc: Option<web::Data<OidcClient>>,
+    pending: web::Data<PendingLogins>,
+    req: HttpRequest,
+    query: web::Query<OidcCallbackQuery>,
+) -> impl Responder {
+    let Some(oidc) = oidc else {
+        return AppError::sso_not_configured().error_response();
+    };
+    if let Some(error) = &query.error {
+        let reason = query.error_description.as_ref().unwrap_or(error);
+        return AppError::unauthorized(format!("Sign-in failed: {}", reason)).error_response();
+    }
+    let (Some(code), Some(state)) = (&query.code, &query.state) else {
+        return AppError::bad_request("Missing code or state").error_response();
+    };
+    // Checked before taking the sign-in, so a foreign callback cannot use it up
+    if !oidc::started_in_this_browser(&req, state) {
+        return AppError::bad_request("This sign-in was started in another browser").error_response();
+    }
+    let Some(request) = pending.take(state) else {
+        return AppError::bad_request("Unknown or expired sign-in").error_response();
+    };
+
+    let login = match complete_sso(db.get_ref(), &events, &oidc, &request, code, false).await {
+        Ok(login) => login,
+        Err(err) => return err.error_response(),
+    };
+
+    let mut response = match &oidc.config().post_login_url {
+        Some(post_login_url) => {
+            // The fragment never reaches a server, so the token stays out of access logs
+            let fragment = url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("token", &login.token)
+                .append_pair("name", &login.name)
+                .append_pair("role", &login.role)
+                .append_pair("email", &login.email)
+                .finish();
+            HttpResponse::Found()
+                .insert_header((LOCATION, format!("{}#{}", post_login_url, fragment)))
+                .insert_header((CACHE_CONTROL, "no-store"))
+                .finish()
+        }
+        None => HttpResponse::Ok().insert_header((CACHE_CONTROL, "no-store")).json(login),
+    };
+    // The sign-in is used up, so its cookie is too
+    let _ = response.add_removal_cookie(&oidc::state_cookie(state, false));
+    response
+}
+
+/// Start a single sign-on for a native app that receives the code on a loopback redirect.
+#[utoipa::path(
+    post,
+    path = "/auth/oidc/start",
+    tag = "auth",
+    request_body = OidcStartRequest,
+    responses(
+        (status = 200, description = "Sign-in started; open the URL in a browser", body = OidcStartResponse),
+        (status = 400, description = "Redirect URI is not a loopback address"),
+        (status = 404, description = "Single sign-on is not configured"),
+    )
+)]
+pub async fn oidc_start(
+    oidc: Option<web::Data<OidcClient>>,
+    pending: web::Data<PendingLogins>,
+    form: web::Json<OidcStartRequest>,
+) -> impl Responder {
+    let Some(oidc) = oidc else {
+        return AppError::sso_not_configured().error_response();
+    };
+    // Anything else would let a caller have codes delivered to a site of their choosing
+    if !oidc::is_loopback_redirect(&form.redirect_uri) {
+        return AppError::bad_request("redirect_uri must be an http://127.0.0.1 or http://localhost address").error_response();
+    }
+
+    match oidc.authorization_request(&form.redirect_uri).await {
+        Ok(request) => {
+            let response = OidcStartResponse { authorization_url: request.url.clone(), state: request.state.clone() };
+            pending.insert(request);
+            HttpResponse::Ok().json(response)
+        }
+        Err(err) => err.error_response(),
+    }
+}
+
+/// Finish a single sign-on started with `/auth/oidc/start`.
+#[utoipa::path(
+    post,
+    path = "/auth/oidc/exchange",
+    tag = "auth",
+    request_body = OidcExchangeRequest,
+    responses(
+        (status = 200, description = "Signed in", body = LoginResponse),
+        (status = 400, description = "Unknown or expired sign-in"),
+        (status = 401, description = "The identity provider refused the code"),
+        (status = 403, description = "No account for this identity"),
+        (status = 404, description = "Single sign-on is not configured"),
+    )
+)]
+pub async fn oidc_exchange(
+    db: web::Data<MySqlPool>,
+    events: web::Data<EventBus>,
+    oidc: Option<web::Data<OidcClient>>,
+    pending: web::Data<PendingLogins>,
+    form: web::Json<OidcExchangeRequest>,
+) -> impl Responder {
+    let Some(oidc) = oidc else {
+        return AppError::sso_not_configured().error_response();
+    };
+    let Some(request) = pending.take(&form.state) else {
+        return AppError::bad_request("Unknown or expired sign-in").error_response();
+    };
+
+    match complete_sso(db.get_ref(), &events, &oidc, &request, &form.code, form.remember_me).await {
+        Ok(login) => HttpResponse::Ok().json(login),
+        Err(err) => err.error_response(),
+    }
+}
+
+// Shared by both flows: redeems the code, signs the user in and issues the session.
+async fn complete_sso(
+    db: &MySqlPool,
+    events: &EventBus,
+    oidc: &OidcClient,
+    request: &AuthRequest,
+    code: &str,
+    remember_me: bool,
+) -> Result<LoginResponse, AppError> {
+    let (user, created) = oidc.finish(db, request, code).await?;
+    if created {
+        events.publish_created(db, user.id as u64).await;
+    }
+
+    let refresh_token = if remember_me {
+        Some(refresh_tokens::issue(db, user.id).await?)
+    } else {
+        None
+    };
+    let token = create_jwt(&user).map_err(AppError::internal)?;
+
+    Ok(LoginResponse { token, name: user.name, email: user.email, role: user.role, refresh_token })
+}
+
 /// Protected route to test authenticated access.
+#[utoipa::path(
+    get,
+    path = "/api/protected",
+    tag = "auth",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "Claims of the presented token", body = Claims),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
 pub async fn protected(req: HttpRequest) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
         HttpResponse::Ok().json(claims)
@@ -58,56 +367,129 @@ pub async fn protected(req: HttpRequest) -> impl Responder {
 }
 
 /// Create a new user (Admins only).
+/// Deprecated alias of `POST /api/v1/users`.
+#[utoipa::path(
+    post,
+    path = "/api/users/create_user",
+    tag = "users",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body = NewUser,
+    responses(
+        (status = 201, description = "User created"),
+        (status = 400, description = "Missing fields or invalid role"),
+        (status = 403, description = "Admins only"),
+        (status = 409, description = "Email taken, possibly by a deleted user", body = AppError),
+    )
+)]
 pub async fn create_user(
     db_pool: web::Data<MySqlPool>,
+    events: web::Data<EventBus>,
+    req: HttpRequest,
+    user: web::Json<NewUser>,
+) -> impl Responder {
+    match insert_new_user(db_pool.get_ref(), &events, &req, &user).await {
+        Ok(_) => HttpResponse::Created().body("User created successfully"),
+        Err(resp) => resp,
+    }
+}
+
+/// Create a new user (Admins only).
+#[utoipa::path(
+    post,
+    path = "/api/v1/users",
+    tag = "users",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body = NewUser,
+    responses(
+        (status = 201, description = "User created", body = PublicUser, headers(
+            ("Location" = String, description = "URL of the new user"),
+            ("ETag" = String, description = "Row version"),
+        )),
+        (status = 400, description = "Missing fields or invalid role"),
+        (status = 403, description = "Admins only"),
+        (status = 409, description = "Email taken, possibly by a deleted user", body = AppError),
+    )
+)]
+pub async fn create_user_v1(
+    db_pool: web::Data<MySqlPool>,
+    events: web::Data<EventBus>,
     req: HttpRequest,
     user: web::Json<NewUser>,
 ) -> impl Responder {
+    match insert_new_user(db_pool.get_ref(), &events, &req, &user).await {
+        Ok(created) => HttpResponse::Created()
+            .insert_header((LOCATION, format!("/api/v1/users/{}", created.id)))
+            .insert_header((ETAG, etag(created.version)))
+            .json(created),
+        Err(resp) => resp,
+    }
+}
+
+// Shared by both create routes; returns the new user or the response to send instead.
+async fn insert_new_user(
+    db: &MySqlPool,
+    events: &EventBus,
+    req: &HttpRequest,
+    user: &NewUser,
+) -> Result<PublicUser, HttpResponse> {
     // Check if the request has valid token claims and that the role is "admin"
     let claims_opt = req.extensions().get::<Claims>().cloned();
     if claims_opt.is_none() {
-        return HttpResponse::Unauthorized().body("Missing token claims");
+        return Err(HttpResponse::Unauthorized().body("Missing token claims"));
     }
 
     let claims = claims_opt.unwrap();
     if claims.role != "admin" {
-        return HttpResponse::Forbidden().body("Admins only can create new users");
+        return Err(HttpResponse::Forbidden().body("Admins only can create new users"));
     }
 
     // Validate the input fields
     if user.name.trim().is_empty() || user.email.trim().is_empty() || user.role.trim().is_empty() {
-        return HttpResponse::BadRequest().body("All fields are required");
+        return Err(HttpResponse::BadRequest().body("All fields are required"));
     }
 
     // Validate role
-    if user.role != "admin" && user.role != "user" {
-        return HttpResponse::BadRequest().body("Invalid role. Only 'admin' or 'user' roles are allowed.");
+    if let Err(err) = validate_role(&user.role) {
+        return Err(err.error_response());
     }
 
     // Hash the password before inserting it into the database
-    let hashed_password = hash(&user.password, DEFAULT_COST).unwrap_or_else(|_| "".to_string());
+    let hashed_password = match hash_password(&user.password) {
+        Ok(h) => h,
+        Err(_) => return Err(HttpResponse::InternalServerError().body("Error creating user")),
+    };
 
-    // Proceed with creating the user
-    let result = sqlx::query!(
-        "INSERT INTO users (name, email, password, role) VALUES (?, ?, ?, ?)",
-        user.name,
-        user.email,
-        hashed_password,
-        user.role
-    )
-    .execute(db_pool.get_ref())
-    .await;
+    // Proceed with creating the user, then read it back for the response
+    let created = match user_repo::insert_user(db, user, &hashed_password).await {
+        Ok(id) => user_repo::find_public_user(db, id as i32).await.map_err(AppError::from),
+        Err(err) => Err(err),
+    };
 
-    match result {
-        Ok(_) => HttpResponse::Created().body("User created successfully"),
+    match created {
+        Ok(Some(created)) => {
+            events.publish(UserEvent::Created(created.clone()));
+            Ok(created)
+        }
+        Ok(None) => Err(HttpResponse::InternalServerError().body("Error creating user")),
+        Err(err) if err.status_code().is_client_error() => Err(err.error_response()),
         Err(err) => {
-            eprintln!("Error creating user: {}", err); // Log the error for debugging
-            HttpResponse::InternalServerError().body("Error creating user")
+            tracing::error!(error = %err, "Error creating user");
+            Err(HttpResponse::InternalServerError().body("Error creating user"))
         }
     }
 }
 
 /// Fetch all users (Admins only).
+#[utoipa::path(
+    get,
+    path = "/api/v1/users",
+    tag = "users",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "Active users", body = [PublicUser]),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
 pub async fn fetch_all_users(
     db: web::Data<MySqlPool>,
     req: HttpRequest,
@@ -120,30 +502,236 @@ pub async fn fetch_all_users(
 
     // Log the user fetching data
     let claims = claims_opt.unwrap();
-    println!("User '{}' with role '{}' is fetching users", claims.sub, claims.role);
+    tracing::debug!(user = %claims.sub, role = %claims.role, "Fetching users");
 
     // Fetch all users
-    match sqlx::query_as!(
-        PublicUser,
-        "SELECT id, name, email, role FROM users"
-    )
-    .fetch_all(db.get_ref())
-    .await
-    {
+    match user_repo::list_users(db.get_ref()).await {
         Ok(users) => HttpResponse::Ok().json(users),
         Err(e) => {
-            eprintln!("DB error: {}", e);
+            tracing::error!(error = %e, "DB error");
             HttpResponse::InternalServerError().body("Failed to fetch users")
         }
     }
 }
 
+/// Stream user changes as Server-Sent Events.
+/// Clients resume with `Last-Event-ID` (or `?last_event_id=`); a `reset` event means
+/// changes were missed and the user list has to be fetched again.
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/events",
+    tag = "users",
+    security(("bearer" = []), ("api_key" = [])),
+    params(
+        FeedQuery,
+        ("Last-Event-ID" = Option<u64>, Header, description = "Id of the last event received"),
+    ),
+    responses(
+        (status = 200, description = "`text/event-stream` of `user-created`, `user-updated`, `user-deleted` and `reset` events", content_type = "text/event-stream", body = PublicUser),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn user_events(
+    events: web::Data<EventBus>,
+    req: HttpRequest,
+    query: web::Query<FeedQuery>,
+) -> impl Responder {
+    // Check that a valid token exists
+    let claims = match req.extensions().get::<Claims>().cloned() {
+        Some(c) => c,
+        None => return HttpResponse::Unauthorized().body("Missing token claims"),
+    };
+
+    let last_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .or(query.last_event_id);
+
+    let (pending, reset, rx) = match last_id {
+        Some(id) => match events.resume(id) {
+            (Backlog::Events(missed), rx) => (missed.into(), false, rx),
+            (Backlog::Gap, rx) => (VecDeque::new(), true, rx),
+        },
+        None => (VecDeque::new(), false, events.subscribe()),
+    };
+
+    let feed = ChangeFeed {
+        pending,
+        reset,
+        rx,
+        keep_alive: interval(Duration::from_secs(15)),
+        role: claims.role,
+        email: claims.sub,
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(stream::unfold(feed, next_frame))
+}
+
+/// Per-connection state of the user change feed.
+struct ChangeFeed {
+    pending: VecDeque<SequencedEvent>,
+    reset: bool,
+    rx: broadcast::Receiver<SequencedEvent>,
+    keep_alive: Interval,
+    role: String,
+    email: String,
+}
+
+/// Produces the next SSE frame the caller is allowed to see, or ends the stream on shutdown.
+async fn next_frame(mut feed: ChangeFeed) -> Option<(Result<web::Bytes, actix_web::Error>, ChangeFeed)> {
+    loop {
+        if feed.reset {
+            feed.reset = false;
+            return Some((Ok(web::Bytes::from_static(b"event: reset\ndata: {}\n\n")), feed));
+        }
+
+        let next = match feed.pending.pop_front() {
+            Some(e) => e,
+            None => tokio::select! {
+                received = feed.rx.recv() => match received {
+                    Ok(e) => e,
+                    // Too slow to keep up; the client reloads instead of missing changes silently
+                    Err(RecvError::Lagged(_)) => {
+                        feed.reset = true;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+                _ = feed.keep_alive.tick() => {
+                    return Some((Ok(web::Bytes::from_static(b": keep-alive\n\n")), feed));
+                }
+            },
+        };
+
+        if !can_view_user_changes(&feed.role, &feed.email, next.event.user()) {
+            continue;
+        }
+
+        let data = serde_json::to_string(next.event.user()).unwrap_or_default();
+        let frame = format!("id: {}\nevent: {}\ndata: {}\n\n", next.id, next.event.name(), data);
+        return Some((Ok(web::Bytes::from(frame)), feed));
+    }
+}
+
+/// Formats a user's row version as a strong ETag.
+fn etag(version: i32) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Reads the expected row version from the `If-Match` header.
+/// A missing header or one that is not one of our ETags is 428; a weak ETag is 412,
+/// since `If-Match` only compares strong validators (RFC 9110 §13.1.1).
+fn if_match_version(req: &HttpRequest) -> Result<i32, AppError> {
+    let missing = || AppError::precondition_required("If-Match header with the user's ETag is required");
+    let value = req.headers().get(IF_MATCH).and_then(|v| v.to_str().ok()).ok_or_else(missing)?.trim();
+    if value.starts_with("W/") {
+        return Err(AppError::precondition_failed("If-Match needs the user's strong ETag, not a weak one"));
+    }
+    value.trim_matches('"').parse().map_err(|_| missing())
+}
+
+/// Fetch a single user by id.
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    security(("bearer" = []), ("api_key" = [])),
+    params(("id" = i32, Path, description = "User id")),
+    responses(
+        (status = 200, description = "The user", body = PublicUser, headers(("ETag" = String, description = "Row version"))),
+        (status = 404, description = "User not found", body = AppError),
+    )
+)]
+pub async fn get_user(
+    db: web::Data<MySqlPool>,
+    req: HttpRequest,
+    user_id: web::Path<i32>,
+) -> impl Responder {
+    // Check that a valid token exists
+    if req.extensions().get::<Claims>().is_none() {
+        return HttpResponse::Unauthorized().body("Missing token claims");
+    }
+
+    match user_repo::find_public_user(db.get_ref(), *user_id).await {
+        Ok(Some(user)) => HttpResponse::Ok()
+            .insert_header((ETAG, etag(user.version)))
+            .json(user),
+        Ok(None) => AppError::user_not_found().error_response(),
+        Err(e) => {
+            tracing::error!(error = %e, "DB error");
+            HttpResponse::InternalServerError().body("Failed to fetch user")
+        }
+    }
+}
+
 /// Update a user's data (Admins only).
+/// Deprecated alias of `PUT /api/v1/users/{id}`.
+#[utoipa::path(
+    put,
+    path = "/api/users/update_user",
+    tag = "users",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body = UpdateUser,
+    params(("If-Match" = String, Header, description = "Strong ETag of the version being replaced")),
+    responses(
+        (status = 200, description = "Updated user", body = PublicUser, headers(("ETag" = String, description = "New row version"))),
+        (status = 400, description = "Missing fields or invalid role"),
+        (status = 403, description = "Admins only"),
+        (status = 404, description = "User not found", body = AppError),
+        (status = 409, description = "Email taken, or would remove the last admin or demote yourself", body = AppError),
+        (status = 412, description = "Stale version (`current` holds the latest state) or weak ETag", body = AppError),
+        (status = 428, description = "If-Match missing", body = AppError),
+    )
+)]
 pub async fn update_user(
     db_pool: web::Data<MySqlPool>,
+    events: web::Data<EventBus>,
     req: HttpRequest,
     user: web::Json<UpdateUser>,
 ) -> impl Responder {
+    replace_user(db_pool.get_ref(), &events, &req, &user).await
+}
+
+/// Replace a user's data (Admins only).
+#[utoipa::path(
+    put,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body = ReplaceUser,
+    params(
+        ("id" = i32, Path, description = "User id"),
+        ("If-Match" = String, Header, description = "Strong ETag of the version being replaced"),
+    ),
+    responses(
+        (status = 200, description = "Updated user", body = PublicUser, headers(("ETag" = String, description = "New row version"))),
+        (status = 400, description = "Missing fields or invalid role"),
+        (status = 403, description = "Admins only"),
+        (status = 404, description = "User not found", body = AppError),
+        (status = 409, description = "Email taken, or would remove the last admin or demote yourself", body = AppError),
+        (status = 412, description = "Stale version (`current` holds the latest state) or weak ETag", body = AppError),
+        (status = 428, description = "If-Match missing", body = AppError),
+    )
+)]
+pub async fn replace_user_v1(
+    db_pool: web::Data<MySqlPool>,
+    events: web::Data<EventBus>,
+    req: HttpRequest,
+    user_id: web::Path<i32>,
+    user: web::Json<ReplaceUser>,
+) -> impl Responder {
+    let user = user.into_inner();
+    let update = UpdateUser { id: *user_id, name: user.name, email: user.email, role: user.role, version: None };
+    replace_user(db_pool.get_ref(), &events, &req, &update).await
+}
+
+// Shared by both update routes.
+async fn replace_user(db: &MySqlPool, events: &EventBus, req: &HttpRequest, user: &UpdateUser) -> HttpResponse {
     // Check if token claims are present and validate role
     let claims_opt = req.extensions().get::<Claims>().cloned();
     if claims_opt.is_none() {
@@ -159,29 +747,180 @@ pub async fn update_user(
     if user.name.trim().is_empty() || user.email.trim().is_empty() || user.role.trim().is_empty() {
         return HttpResponse::BadRequest().body("All fields are required");
     }
+    if let Err(err) = validate_role(&user.role) {
+        return err.error_response();
+    }
+
+    // Require the client to say which version it edited
+    let expected_version = match if_match_version(req) {
+        Ok(v) => v,
+        Err(err) => return err.error_response(),
+    };
+
+    // Update user in the database and return the updated record
+    match user_repo::update_user(db, user, expected_version, claims.uid).await {
+        Ok(UpdateOutcome::Updated(updated)) => {
+            events.publish(UserEvent::Updated(updated.clone()));
+            HttpResponse::Ok()
+                .insert_header((ETAG, etag(updated.version)))
+                .json(updated)
+        }
+        Ok(UpdateOutcome::NotFound) => AppError::user_not_found().error_response(),
+        Ok(UpdateOutcome::Stale(current)) => AppError::conflict(current).error_response(),
+        Err(err) => err.error_response(),
+    }
+}
 
-    // Update user in the database
-    let result = sqlx::query!(
-        "UPDATE users SET name = ?, email = ?, role = ? WHERE id = ?",
-        user.name,
-        user.email,
-        user.role,
-        user.id
+/// Partially update a user with JSON Merge Patch semantics.
+/// Each present field is validated and authorized separately.
+#[utoipa::path(
+    patch,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body(content = PatchUser, content_type = "application/merge-patch+json"),
+    params(
+        ("id" = i32, Path, description = "User id"),
+        ("If-Match" = String, Header, description = "Strong ETag of the version being patched"),
+    ),
+    responses(
+        (status = 200, description = "Patched user", body = PublicUser, headers(("ETag" = String, description = "New row version"))),
+        (status = 400, description = "Invalid field", body = AppError),
+        (status = 403, description = "Not allowed to change a field", body = AppError),
+        (status = 404, description = "User not found", body = AppError),
+        (status = 409, description = "Email taken, or would remove the last admin or demote yourself", body = AppError),
+        (status = 412, description = "Stale version (`current` holds the latest state) or weak ETag", body = AppError),
+        (status = 428, description = "If-Match missing", body = AppError),
     )
-    .execute(db_pool.get_ref())
-    .await;
+)]
+pub async fn patch_user(
+    db_pool: web::Data<MySqlPool>,
+    events: web::Data<EventBus>,
+    req: HttpRequest,
+    user_id: web::Path<i32>,
+    patch: web::Json<PatchUser>,
+) -> impl Responder {
+    // Check if token claims are present
+    let claims = match req.extensions().get::<Claims>().cloned() {
+        Some(c) => c,
+        None => return HttpResponse::Unauthorized().body("Missing token claims"),
+    };
 
-    match result {
-        Ok(_) => HttpResponse::Ok().body("User updated"),
-        Err(_) => HttpResponse::InternalServerError().body("Error updating user"),
+    // Validate and authorize each field in the patch
+    if let Err(err) = validate_patch(&patch).and_then(|_| authorize_patch(&claims.role, &patch)) {
+        return err.error_response();
+    }
+
+    // Require the client to say which version it edited
+    let expected_version = match if_match_version(&req) {
+        Ok(v) => v,
+        Err(err) => return err.error_response(),
+    };
+
+    match user_repo::patch_user(db_pool.get_ref(), *user_id, &patch, expected_version, claims.uid).await {
+        Ok(UpdateOutcome::Updated(updated)) => {
+            events.publish(UserEvent::Updated(updated.clone()));
+            HttpResponse::Ok()
+                .insert_header((ETAG, etag(updated.version)))
+                .json(updated)
+        }
+        Ok(UpdateOutcome::NotFound) => AppError::user_not_found().error_response(),
+        Ok(UpdateOutcome::Stale(current)) => AppError::conflict(current).error_response(),
+        Err(err) => err.error_response(),
     }
 }
 
 /// Delete a user (Admins only).
+/// Deprecated alias of `DELETE /api/v1/users/{id}`.
+#[utoipa::path(
+    delete,
+    path = "/api/users/delete_user/{id}",
+    tag = "users",
+    security(("bearer" = []), ("api_key" = [])),
+    params(("id" = i32, Path, description = "User id")),
+    responses(
+        (status = 200, description = "User moved to trash"),
+        (status = 403, description = "Admins only"),
+        (status = 404, description = "User not found", body = AppError),
+        (status = 409, description = "Would delete yourself or the last admin", body = AppError),
+    )
+)]
 pub async fn delete_user(
     db_pool: web::Data<MySqlPool>,
+    events: web::Data<EventBus>,
+    req: HttpRequest,
+    user_id: web::Path<i32>,
+) -> impl Responder {
+    match trash_user(db_pool.get_ref(), &events, &req, *user_id).await {
+        Ok(()) => HttpResponse::Ok().body("User moved to trash"),
+        Err(resp) => resp,
+    }
+}
+
+/// Move a user to the trash (Admins only).
+#[utoipa::path(
+    delete,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    security(("bearer" = []), ("api_key" = [])),
+    params(("id" = i32, Path, description = "User id")),
+    responses(
+        (status = 204, description = "User moved to trash"),
+        (status = 403, description = "Admins only"),
+        (status = 404, description = "User not found", body = AppError),
+        (status = 409, description = "Would delete yourself or the last admin", body = AppError),
+    )
+)]
+pub async fn delete_user_v1(
+    db_pool: web::Data<MySqlPool>,
+    events: web::Data<EventBus>,
     req: HttpRequest,
     user_id: web::Path<i32>,
+) -> impl Responder {
+    match trash_user(db_pool.get_ref(), &events, &req, *user_id).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(resp) => resp,
+    }
+}
+
+// Shared by both delete routes.
+async fn trash_user(db: &MySqlPool, events: &EventBus, req: &HttpRequest, id: i32) -> Result<(), HttpResponse> {
+    // Check if token claims are present and validate role
+    let claims_opt = req.extensions().get::<Claims>().cloned();
+    if claims_opt.is_none() {
+        return Err(HttpResponse::Unauthorized().body("Missing token claims"));
+    }
+
+    let claims = claims_opt.unwrap();
+    if claims.role != "admin" {
+        return Err(HttpResponse::Forbidden().body("Admins only"));
+    }
+
+    // Delete user from the database
+    match user_repo::delete_user(db, id, claims.uid).await {
+        Ok(Some(user)) => {
+            events.publish(UserEvent::Deleted(user));
+            Ok(())
+        }
+        Ok(None) => Err(AppError::user_not_found().error_response()),
+        Err(err) => Err(err.error_response()),
+    }
+}
+
+/// List soft-deleted users (Admins only).
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/trash",
+    tag = "users",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "Soft-deleted users", body = [DeletedUser]),
+        (status = 403, description = "Admins only"),
+    )
+)]
+pub async fn fetch_deleted_users(
+    db_pool: web::Data<MySqlPool>,
+    req: HttpRequest,
 ) -> impl Responder {
     // Check if token claims are present and validate role
     let claims_opt = req.extensions().get::<Claims>().cloned();
@@ -194,12 +933,433 @@ pub async fn delete_user(
         return HttpResponse::Forbidden().body("Admins only");
     }
 
-    // Delete user from the database
-    match sqlx::query!("DELETE FROM users WHERE id = ?", *user_id)
-        .execute(db_pool.get_ref())
-        .await
+    match user_repo::list_deleted_users(db_pool.get_ref()).await {
+        Ok(users) => HttpResponse::Ok().json(users),
+        Err(e) => {
+            tracing::error!(error = %e, "DB error");
+            HttpResponse::InternalServerError().body("Failed to fetch deleted users")
+        }
+    }
+}
+
+/// Restore a soft-deleted user (Admins only).
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/{id}/restore",
+    tag = "users",
+    security(("bearer" = []), ("api_key" = [])),
+    params(("id" = i32, Path, description = "User id")),
+    responses(
+        (status = 200, description = "Restored user", body = PublicUser, headers(("ETag" = String, description = "Row version"))),
+        (status = 403, description = "Admins only"),
+        (status = 404, description = "No deleted user with that id", body = AppError),
+    )
+)]
+pub async fn restore_user(
+    db_pool: web::Data<MySqlPool>,
+    events: web::Data<EventBus>,
+    req: HttpRequest,
+    user_id: web::Path<i32>,
+) -> impl Responder {
+    // Check if token claims are present and validate role
+    let claims_opt = req.extensions().get::<Claims>().cloned();
+    if claims_opt.is_none() {
+        return HttpResponse::Unauthorized().body("Missing token claims");
+    }
+
+    let claims = claims_opt.unwrap();
+    if claims.role != "admin" {
+        return HttpResponse::Forbidden().body("Admins only");
+    }
+
+    match user_repo::restore_user(db_pool.get_ref(), *user_id).await {
+        Ok(Some(user)) => {
+            events.publish(UserEvent::Created(user.clone()));
+            HttpResponse::Ok()
+                .insert_header((ETAG, etag(user.version)))
+                .json(user)
+        }
+        Ok(None) => AppError::user_not_found().error_response(),
+        Err(_) => HttpResponse::InternalServerError().body("Error restoring user"),
+    }
+}
+
+/// Subscribe a URL to user events (Admins only).
+/// The response carries the signing secret, which is not shown again.
+#[utoipa::path(
+    post,
+    path = "/api/webhooks",
+    tag = "webhooks",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body = NewWebhook,
+    responses(
+        (status = 201, description = "Subscription and its signing secret", body = CreatedWebhook),
+        (status = 400, description = "Invalid URL or event", body = AppError),
+        (status = 403, description = "Admins only"),
+    )
+)]
+pub async fn create_webhook(
+    db_pool: web::Data<MySqlPool>,
+    req: HttpRequest,
+    webhook: web::Json<NewWebhook>,
+) -> impl Responder {
+    // Check if token claims are present and validate role
+    let claims_opt = req.extensions().get::<Claims>().cloned();
+    if claims_opt.is_none() {
+        return HttpResponse::Unauthorized().body("Missing token claims");
+    }
+
+    let claims = claims_opt.unwrap();
+    if claims.role != "admin" {
+        return HttpResponse::Forbidden().body("Admins only");
+    }
+
+    // Validate the target URL and the event names
+    if !(webhook.url.starts_with("https://") || webhook.url.starts_with("http://")) {
+        return AppError::bad_request("The URL must start with http:// or https://").error_response();
+    }
+    if webhook.events.is_empty() || webhook.events.iter().any(|e| !WEBHOOK_EVENTS.contains(&e.as_str())) {
+        return AppError::bad_request(format!("Events must be one or more of: {}", WEBHOOK_EVENTS.join(", ")))
+            .error_response();
+    }
+
+    match webhooks::create_webhook(db_pool.get_ref(), &webhook.url, &webhook.events).await {
+        Ok((webhook, secret)) => HttpResponse::Created().json(CreatedWebhook { webhook, secret }),
+        Err(err) => AppError::from(err).error_response(),
+    }
+}
+
+/// List webhook subscriptions (Admins only).
+#[utoipa::path(
+    get,
+    path = "/api/webhooks",
+    tag = "webhooks",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "Subscriptions", body = [Webhook]),
+        (status = 403, description = "Admins only"),
+    )
+)]
+pub async fn fetch_webhooks(
+    db_pool: web::Data<MySqlPool>,
+    req: HttpRequest,
+) -> impl Responder {
+    // Check if token claims are present and validate role
+    let claims_opt = req.extensions().get::<Claims>().cloned();
+    if claims_opt.is_none() {
+        return HttpResponse::Unauthorized().body("Missing token claims");
+    }
+
+    let claims = claims_opt.unwrap();
+    if claims.role != "admin" {
+        return HttpResponse::Forbidden().body("Admins only");
+    }
+
+    match webhooks::list_webhooks(db_pool.get_ref()).await {
+        Ok(hooks) => HttpResponse::Ok().json(hooks),
+        Err(err) => AppError::from(err).error_response(),
+    }
+}
+
+/// Remove a webhook subscription and its delivery history (Admins only).
+#[utoipa::path(
+    delete,
+    path = "/api/webhooks/{id}",
+    tag = "webhooks",
+    security(("bearer" = []), ("api_key" = [])),
+    params(("id" = i32, Path, description = "Webhook id")),
+    responses(
+        (status = 204, description = "Subscription removed"),
+        (status = 403, description = "Admins only"),
+        (status = 404, description = "Webhook not found"),
+    )
+)]
+pub async fn delete_webhook(
+    db_pool: web::Data<MySqlPool>,
+    req: HttpRequest,
+    webhook_id: web::Path<i32>,
+) -> impl Responder {
+    // Check if token claims are present and validate role
+    let claims_opt = req.extensions().get::<Claims>().cloned();
+    if claims_opt.is_none() {
+        return HttpResponse::Unauthorized().body("Missing token claims");
+    }
+
+    let claims = claims_opt.unwrap();
+    if claims.role != "admin" {
+        return HttpResponse::Forbidden().body("Admins only");
+    }
+
+    match webhooks::delete_webhook(db_pool.get_ref(), *webhook_id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().body("Webhook not found"),
+        Err(err) => AppError::from(err).error_response(),
+    }
+}
+
+/// List recent deliveries of a webhook (Admins only).
+#[utoipa::path(
+    get,
+    path = "/api/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    security(("bearer" = []), ("api_key" = [])),
+    params(("id" = i32, Path, description = "Webhook id")),
+    responses(
+        (status = 200, description = "Latest 100 deliveries, newest first", body = [WebhookDelivery]),
+        (status = 403, description = "Admins only"),
+    )
+)]
+pub async fn fetch_webhook_deliveries(
+    db_pool: web::Data<MySqlPool>,
+    req: HttpRequest,
+    webhook_id: web::Path<i32>,
+) -> impl Responder {
+    // Check if token claims are present and validate role
+    let claims_opt = req.extensions().get::<Claims>().cloned();
+    if claims_opt.is_none() {
+        return HttpResponse::Unauthorized().body("Missing token claims");
+    }
+
+    let claims = claims_opt.unwrap();
+    if claims.role != "admin" {
+        return HttpResponse::Forbidden().body("Admins only");
+    }
+
+    match webhooks::list_deliveries(db_pool.get_ref(), *webhook_id).await {
+        Ok(deliveries) => HttpResponse::Ok().json(deliveries),
+        Err(err) => AppError::from(err).error_response(),
+    }
+}
+
+/// Show the delivery log of a single delivery (Admins only).
+#[utoipa::path(
+    get,
+    path = "/api/webhooks/deliveries/{id}/attempts",
+    tag = "webhooks",
+    security(("bearer" = []), ("api_key" = [])),
+    params(("id" = i64, Path, description = "Delivery id")),
+    responses(
+        (status = 200, description = "Delivery log", body = [DeliveryAttempt]),
+        (status = 403, description = "Admins only"),
+    )
+)]
+pub async fn fetch_delivery_attempts(
+    db_pool: web::Data<MySqlPool>,
+    req: HttpRequest,
+    delivery_id: web::Path<i64>,
+) -> impl Responder {
+    // Check if token claims are present and validate role
+    let claims_opt = req.extensions().get::<Claims>().cloned();
+    if claims_opt.is_none() {
+        return HttpResponse::Unauthorized().body("Missing token claims");
+    }
+
+    let claims = claims_opt.unwrap();
+    if claims.role != "admin" {
+        return HttpResponse::Forbidden().body("Admins only");
+    }
+
+    match webhooks::list_attempts(db_pool.get_ref(), *delivery_id).await {
+        Ok(attempts) => HttpResponse::Ok().json(attempts),
+        Err(err) => AppError::from(err).error_response(),
+    }
+}
+
+/// Put a dead delivery back into the outbox (Admins only).
+#[utoipa::path(
+    post,
+    path = "/api/webhooks/deliveries/{id}/retry",
+    tag = "webhooks",
+    security(("bearer" = []), ("api_key" = [])),
+    params(("id" = i64, Path, description = "Delivery id")),
+    responses(
+        (status = 202, description = "Delivery queued for retry"),
+        (status = 403, description = "Admins only"),
+        (status = 404, description = "No dead delivery with that id"),
+    )
+)]
+pub async fn retry_webhook_delivery(
+    db_pool: web::Data<MySqlPool>,
+    req: HttpRequest,
+    delivery_id: web::Path<i64>,
+) -> impl Responder {
+    // Check if token claims are present and validate role
+    let claims_opt = req.extensions().get::<Claims>().cloned();
+    if claims_opt.is_none() {
+        return HttpResponse::Unauthorized().body("Missing token claims");
+    }
+
+    let claims = claims_opt.unwrap();
+    if claims.role != "admin" {
+        return HttpResponse::Forbidden().body("Admins only");
+    }
+
+    match webhooks::retry_delivery(db_pool.get_ref(), *delivery_id).await {
+        Ok(true) => HttpResponse::Accepted().body("Delivery queued for retry"),
+        Ok(false) => HttpResponse::NotFound().body("No dead delivery with that id"),
+        Err(err) => AppError::from(err).error_response(),
+    }
+}
+
+/// Issue an API key for scripts (Admins only).
+/// The response carries the key, which is not shown again.
+#[utoipa::path(
+    post,
+    path = "/api/keys",
+    tag = "api-keys",
+    security(("bearer" = [])),
+    request_body = NewApiKey,
+    responses(
+        (status = 201, description = "Key and its metadata", body = CreatedApiKey),
+        (status = 400, description = "Invalid name, role, scope or expiry", body = AppError),
+        (status = 403, description = "Admins only"),
+    )
+)]
+pub async fn create_api_key(
+    db_pool: web::Data<MySqlPool>,
+    req: HttpRequest,
+    new_key: web::Json<NewApiKey>,
+) -> impl Responder {
+    // Check if token claims are present and validate role
+    let claims_opt = req.extensions().get::<Claims>().cloned();
+    if claims_opt.is_none() {
+        return HttpResponse::Unauthorized().body("Missing token claims");
+    }
+
+    let claims = claims_opt.unwrap();
+    if claims.role != "admin" {
+        return HttpResponse::Forbidden().body("Admins only");
+    }
+
+    // Validate the name, role, scopes and expiry
+    if new_key.name.trim().is_empty() {
+        return AppError::bad_request("The name cannot be empty").error_response();
+    }
+    if !ROLES.contains(&new_key.role.as_str()) {
+        return AppError::bad_request(format!("Role must be one of: {}", ROLES.join(", "))).error_response();
+    }
+    if new_key.scopes.is_empty() || new_key.scopes.iter().any(|s| !API_KEY_SCOPES.contains(&s.as_str())) {
+        return AppError::bad_request(format!("Scopes must be one or more of: {}", API_KEY_SCOPES.join(", ")))
+            .error_response();
+    }
+    if new_key.expires_at.is_some_and(|at| at <= Utc::now().naive_utc()) {
+        return AppError::bad_request("The expiry must be in the future").error_response();
+    }
+
+    match api_keys::create_api_key(
+        db_pool.get_ref(),
+        new_key.name.trim(),
+        &new_key.role,
+        &new_key.scopes,
+        new_key.expires_at,
+        &claims.sub,
+    )
+    .await
     {
-        Ok(_) => HttpResponse::Ok().body("User deleted"),
-        Err(_) => HttpResponse::InternalServerError().body("Error deleting user"),
+        Ok((api_key, key)) => HttpResponse::Created().json(CreatedApiKey { api_key, key }),
+        Err(err) => AppError::from(err).error_response(),
+    }
+}
+
+/// List API keys, including revoked and expired ones (Admins only).
+#[utoipa::path(
+    get,
+    path = "/api/keys",
+    tag = "api-keys",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Keys, newest first", body = [ApiKey]),
+        (status = 403, description = "Admins only"),
+    )
+)]
+pub async fn fetch_api_keys(
+    db_pool: web::Data<MySqlPool>,
+    req: HttpRequest,
+) -> impl Responder {
+    // Check if token claims are present and validate role
+    let claims_opt = req.extensions().get::<Claims>().cloned();
+    if claims_opt.is_none() {
+        return HttpResponse::Unauthorized().body("Missing token claims");
+    }
+
+    let claims = claims_opt.unwrap();
+    if claims.role != "admin" {
+        return HttpResponse::Forbidden().body("Admins only");
+    }
+
+    match api_keys::list_api_keys(db_pool.get_ref()).await {
+        Ok(keys) => HttpResponse::Ok().json(keys),
+        Err(err) => AppError::from(err).error_response(),
+    }
+}
+
+/// Revoke an API key; requests using it are rejected from then on (Admins only).
+#[utoipa::path(
+    delete,
+    path = "/api/keys/{id}",
+    tag = "api-keys",
+    security(("bearer" = [])),
+    params(("id" = i32, Path, description = "API key id")),
+    responses(
+        (status = 204, description = "Key revoked"),
+        (status = 403, description = "Admins only"),
+        (status = 404, description = "No active key with that id"),
+    )
+)]
+pub async fn revoke_api_key(
+    db_pool: web::Data<MySqlPool>,
+    req: HttpRequest,
+    key_id: web::Path<i32>,
+) -> impl Responder {
+    // Check if token claims are present and validate role
+    let claims_opt = req.extensions().get::<Claims>().cloned();
+    if claims_opt.is_none() {
+        return HttpResponse::Unauthorized().body("Missing token claims");
+    }
+
+    let claims = claims_opt.unwrap();
+    if claims.role != "admin" {
+        return HttpResponse::Forbidden().body("Admins only");
+    }
+
+    match api_keys::revoke_api_key(db_pool.get_ref(), *key_id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().body("No active key with that id"),
+        Err(err) => AppError::from(err).error_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    fn with_if_match(value: &str) -> HttpRequest {
+        TestRequest::default().insert_header((IF_MATCH, value)).to_http_request()
+    }
+
+    #[test]
+    fn strong_etag_is_the_expected_version() {
+        assert_eq!(if_match_version(&with_if_match("\"3\"")).unwrap(), 3);
+        assert_eq!(if_match_version(&with_if_match(" \"12\" ")).unwrap(), 12);
+    }
+
+    #[test]
+    fn weak_etag_is_rejected_with_412() {
+        let err = if_match_version(&with_if_match("W/\"3\"")).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(err.code, "PRECONDITION_FAILED");
+    }
+
+    #[test]
+    fn missing_or_foreign_etag_is_rejected_with_428() {
+        let missing = if_match_version(&TestRequest::default().to_http_request()).unwrap_err();
+        assert_eq!(missing.status_code(), StatusCode::PRECONDITION_REQUIRED);
+
+        for value in ["*", "\"abc\"", ""] {
+            let err = if_match_version(&with_if_match(value)).unwrap_err();
+            assert_eq!(err.status_code(), StatusCode::PRECONDITION_REQUIRED, "{:?}", value);
+        }
     }
 }
@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use actix_web::{web, HttpResponse, Responder};
+use serde::Serialize;
+use sqlx::MySqlPool;
+use utoipa::ToSchema;
+
+use crate::utils::auth::signing_key_available;
+use crate::utils::server::MIGRATOR;
+
+/// How long a readiness check may wait on the database.
+const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Result of `/readyz`; each check is `"ok"` or a short reason.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    pub database: String,
+    pub migrations: String,
+    pub signing_key: String,
+}
+
+/// Build information of the running server.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+}
+
+/// Liveness probe: the process is up and serving requests.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "ops",
+    responses((status = 200, description = "Alive"), (status = 429, description = "Rate limited"))
+)]
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok().body("ok")
+}
+
+/// Readiness probe: the database is reachable, every migration is applied and a signing key is set.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "ops",
+    responses(
+        (status = 200, description = "Ready to serve traffic", body = Readiness),
+        (status = 503, description = "Not ready; the failing checks say why", body = Readiness),
+        (status = 429, description = "Rate limited"),
+    )
+)]
+pub async fn readyz(db: web::Data<MySqlPool>) -> impl Responder {
+    let database = check(ping(db.get_ref())).await;
+    let migrations = if database == "ok" {
+        check(pending_migrations(db.get_ref())).await
+    } else {
+        "skipped: database unavailable".to_string()
+    };
+    let signing_key = if signing_key_available() {
+        "ok".to_string()
+    } else {
+        "JWT_SECRET not set".to_string()
+    };
+
+    let ready = database == "ok" && migrations == "ok" && signing_key == "ok";
+    let body = Readiness { ready, database, migrations, signing_key };
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+/// Crate version and the git commit it was built from.
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "ops",
+    responses((status = 200, description = "Build information", body = BuildInfo), (status = 429, description = "Rate limited"))
+)]
+pub async fn version() -> impl Responder {
+    HttpResponse::Ok().json(BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("GIT_HASH"),
+    })
+}
+
+// Runs a database check with a timeout and turns its outcome into "ok" or a reason.
+async fn check(fut: impl std::future::Future<Output = Result<(), String>>) -> String {
+    match tokio::time::timeout(DB_CHECK_TIMEOUT, fut).await {
+        Ok(Ok(())) => "ok".to_string(),
+        Ok(Err(reason)) => reason,
+        Err(_) => "timed out".to_string(),
+    }
+}
+
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+async fn ping(db: &MySqlPool) -> Result<(), String> {
+    sqlx::query("SELECT 1")
+        .execute(db)
+        .await
+        .map(drop)
+        .map_err(|_| "unreachable".to_string())
+}
+
+// Compares the migrations compiled into the binary with the ones recorded as applied.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+async fn pending_migrations(db: &MySqlPool) -> Result<(), String> {
+    let applied: HashSet<i64> = sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = 1")
+        .fetch_all(db)
+        .await
+        .map_err(|_| "migration table unreadable".to_string())?
+        .into_iter()
+        .collect();
+
+    let pending = MIGRATOR.iter().filter(|m| !applied.contains(&m.version)).count();
+    if pending == 0 {
+        Ok(())
+    } else {
+        Err(format!("{} pending", pending))
+    }
+}
@@ -0,0 +1,219 @@
+use ldap3::{drive, ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use sqlx::MySqlPool;
+
+use crate::utils::app_error::AppError;
+use crate::utils::auth::verify_password;
+use crate::utils::config::LdapConfig;
+use crate::utils::models::User;
+use crate::utils::provisioning::{sign_in_external, ExternalUser, ProvisioningPolicy};
+use crate::utils::user_repo::{self, ExternalId};
+
+/// LDAP result code for a failed bind.
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// A directory user whose password was just verified.
+#[derive(Debug, Clone)]
+pub struct LdapIdentity {
+    pub dn: String,
+    pub email: String,
+    pub name: String,
+    /// DNs of the groups the user is in.
+    pub groups: Vec<String>,
+}
+
+/// What the directory said about a login.
+#[derive(Debug)]
+pub enum LdapLogin {
+    Authenticated(LdapIdentity),
+    /// The user is in the directory but the password is wrong.
+    InvalidCredentials,
+    /// No directory entry matches the login; it may be a local account.
+    NotInDirectory,
+}
+
+/// Logs in with a password: through the directory if `config` is set and knows the login,
+/// otherwise against the local password. Returns the user and whether it was created just now,
+/// or `None` for wrong credentials.
+pub async fn password_login(
+    db: &MySqlPool,
+    config: Option<&LdapConfig>,
+    login: &str,
+    password: &str,
+) -> Result<Option<(User, bool)>, AppError> {
+    if let Some(config) = config {
+        match authenticate(config, login, password).await {
+            Ok(LdapLogin::Authenticated(identity)) => return sign_in(db, config, &identity).await.map(Some),
+            // The directory is authoritative for its users; no fallback to a stale local password
+            Ok(LdapLogin::InvalidCredentials) => return Ok(None),
+            Ok(LdapLogin::NotInDirectory) => {}
+            // An outage must not lock out local accounts; directory users are refused below
+            Err(e) => tracing::warn!(error = %e, "LDAP directory unavailable, trying the local password"),
+        }
+    }
+
+    let Some(user) = user_repo::find_local_user_by_email(db, login).await? else {
+        return Ok(None);
+    };
+    let verified = verify_password(password, &user.password).map_err(AppError::internal)?;
+    Ok(verified.then_some((user, false)))
+}
+
+/// Looks the login up in the directory and binds as the entry found to check the password.
+#[tracing::instrument(name = "ldap.authenticate", skip_all, fields(otel.kind = "client"))]
+pub async fn authenticate(config: &LdapConfig, login: &str, password: &str) -> Result<LdapLogin, AppError> {
+    // Servers accept a bind with an empty password as an anonymous one
+    if password.is_empty() {
+        return Ok(LdapLogin::InvalidCredentials);
+    }
+
+    let settings = LdapConnSettings::new()
+        .set_conn_timeout(config.timeout)
+        .set_starttls(config.starttls);
+    let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &config.url)
+        .await
+        .map_err(|e| AppError::internal(format!("Could not connect to {}: {}", config.url, e)))?;
+    drive!(conn);
+
+    let result = lookup_and_bind(&mut ldap, config, login, password).await;
+    let _ = ldap.unbind().await;
+    result.map_err(|e| AppError::internal(format!("LDAP: {}", e)))
+}
+
+async fn lookup_and_bind(
+    ldap: &mut Ldap,
+    config: &LdapConfig,
+    login: &str,
+    password: &str,
+) -> Result<LdapLogin, ldap3::LdapError> {
+    if let Some(bind_dn) = &config.bind_dn {
+        ldap.with_timeout(config.timeout)
+            .simple_bind(bind_dn, config.bind_password.as_deref().unwrap_or(""))
+            .await?
+            .success()?;
+    }
+
+    let filter = search_filter(&config.user_filter, "{login}", login);
+    let attributes = vec![
+        config.email_attribute.as_str(),
+        config.name_attribute.as_str(),
+        config.group_attribute.as_str(),
+    ];
+    let (entries, _) = ldap
+        .with_timeout(config.timeout)
+        .search(&config.user_base_dn, Scope::Subtree, &filter, attributes)
+        .await?
+        .success()?;
+    let mut entries = entries.into_iter().map(SearchEntry::construct);
+    let entry = match (entries.next(), entries.next()) {
+        (Some(entry), None) => entry,
+        (None, _) => return Ok(LdapLogin::NotInDirectory),
+        // Binding as either could log the user in as someone else
+        (Some(_), Some(_)) => {
+            tracing::warn!(%filter, "Login matches more than one directory entry");
+            return Ok(LdapLogin::InvalidCredentials);
+        }
+    };
+
+    let mut groups = attribute(&entry, &config.group_attribute).to_vec();
+    // Looked up with the search account, which may see groups the user cannot
+    if let Some(group_base_dn) = &config.group_base_dn {
+        let filter = search_filter(&config.group_filter, "{dn}", &entry.dn);
+        let (found, _) = ldap
+            .with_timeout(config.timeout)
+            .search(group_base_dn, Scope::Subtree, &filter, vec!["1.1"])
+            .await?
+            .success()?;
+        for group in found.into_iter().map(SearchEntry::construct) {
+            if !groups.iter().any(|dn| dn.eq_ignore_ascii_case(&group.dn)) {
+                groups.push(group.dn);
+            }
+        }
+    }
+
+    let bind = ldap.with_timeout(config.timeout).simple_bind(&entry.dn, password).await?;
+    if bind.rc == INVALID_CREDENTIALS {
+        return Ok(LdapLogin::InvalidCredentials);
+    }
+    bind.success()?;
+
+    // Directories without a mail attribute are searched by email, so the login is one
+    let email = attribute(&entry, &config.email_attribute)
+        .first()
+        .cloned()
+        .unwrap_or_else(|| login.to_string());
+    let name = attribute(&entry, &config.name_attribute)
+        .first()
+        .cloned()
+        .unwrap_or_else(|| email.clone());
+
+    Ok(LdapLogin::Authenticated(LdapIdentity { dn: entry.dn, email, name, groups }))
+}
+
+// The value is escaped, so whatever the user typed cannot change the filter.
+fn search_filter(template: &str, placeholder: &str, value: &str) -> String {
+    template.replace(placeholder, &ldap_escape(value))
+}
+
+// Servers may return attribute names in another case than they were requested in.
+fn attribute<'a>(entry: &'a SearchEntry, name: &str) -> &'a [String] {
+    entry
+        .attrs
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, values)| values.as_slice())
+        .unwrap_or_default()
+}
+
+/// Finds or creates the local user for a directory entry; see `provisioning::sign_in_external`.
+/// Links by email only if `LDAP_LINK_BY_EMAIL` is on. The name follows the directory.
+pub async fn sign_in(db: &MySqlPool, config: &LdapConfig, identity: &LdapIdentity) -> Result<(User, bool), AppError> {
+    let external = ExternalUser {
+        id: ExternalId::LdapDn(&identity.dn),
+        email: &identity.email,
+        name: &identity.name,
+        mapped_role: config.role_for_groups(&identity.groups),
+    };
+    let policy = ProvisioningPolicy {
+        auto_provision: config.auto_provision,
+        link_by_email: config.link_by_email,
+        roles_from_groups: !config.role_mapping.is_empty(),
+        default_role: &config.default_role,
+    };
+    let (mut user, created) = sign_in_external(db, &external, &policy).await?;
+
+    if user.name != identity.name {
+        user_repo::set_name(db, user.id, &identity.name).await?;
+        user.name = identity.name.clone();
+    }
+
+    Ok((user, created))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_is_inserted_into_the_filter() {
+        assert_eq!(search_filter("(mail={login})", "{login}", "alice@example.org"), "(mail=alice@example.org)");
+    }
+
+    #[test]
+    fn filter_metacharacters_are_escaped() {
+        assert_eq!(search_filter("(mail={login})", "{login}", "*"), "(mail=\\2a)");
+        assert_eq!(
+            search_filter("(&(objectClass=person)(mail={login}))", "{login}", "*)(uid=*"),
+            "(&(objectClass=person)(mail=\\2a\\29\\28uid=\\2a))"
+        );
+        assert_eq!(search_filter("(mail={login})", "{login}", "a\\b\0"), "(mail=a\\5cb\\00)");
+    }
+
+    #[test]
+    fn group_filter_escapes_the_dn() {
+        assert_eq!(
+            search_filter("(member={dn})", "{dn}", "cn=Smith\\, John (IT),ou=people,dc=example,dc=org"),
+            "(member=cn=Smith\\5c, John \\28IT\\29,ou=people,dc=example,dc=org)"
+        );
+    }
+}
+
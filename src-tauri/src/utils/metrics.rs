@@ -0,0 +1,194 @@
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    web, Error, HttpResponse, Responder,
+};
+use actix_web::middleware::Next;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use sqlx::MySqlPool;
+
+/// Process-wide Prometheus collectors, exposed at `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    logins: IntCounterVec,
+    jwt_failures: IntCounterVec,
+    bcrypt_duration: HistogramVec,
+    db_connections: IntGaugeVec,
+    db_max_connections: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by method, route pattern and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by method, route pattern and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let logins = IntCounterVec::new(
+            Opts::new("login_attempts_total", "Password logins by outcome"),
+            &["outcome"],
+        )
+        .unwrap();
+        let jwt_failures = IntCounterVec::new(
+            Opts::new("jwt_validation_failures_total", "Rejected bearer tokens by reason"),
+            &["reason"],
+        )
+        .unwrap();
+        // bcrypt is deliberately slow, so the default buckets would lump everything together
+        let bcrypt_duration = HistogramVec::new(
+            HistogramOpts::new("bcrypt_duration_seconds", "Time spent hashing or verifying passwords")
+                .buckets(vec![0.05, 0.1, 0.2, 0.3, 0.5, 0.75, 1.0, 2.0]),
+            &["op"],
+        )
+        .unwrap();
+        let db_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "MySQL pool connections by state"),
+            &["state"],
+        )
+        .unwrap();
+        let db_max_connections = IntGauge::new("db_pool_max_connections", "Most connections the MySQL pool opens").unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_duration.clone())).unwrap();
+        registry.register(Box::new(logins.clone())).unwrap();
+        registry.register(Box::new(jwt_failures.clone())).unwrap();
+        registry.register(Box::new(bcrypt_duration.clone())).unwrap();
+        registry.register(Box::new(db_connections.clone())).unwrap();
+        registry.register(Box::new(db_max_connections.clone())).unwrap();
+
+        Metrics {
+            registry,
+            http_requests,
+            http_duration,
+            logins,
+            jwt_failures,
+            bcrypt_duration,
+            db_connections,
+            db_max_connections,
+        }
+    }
+}
+
+/// The process-wide collectors, created on first use.
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+/// Counts a password login attempt.
+pub fn record_login(success: bool) {
+    let outcome = if success { "success" } else { "failure" };
+    metrics().logins.with_label_values(&[outcome]).inc();
+}
+
+/// Counts a rejected bearer token, e.g. `missing`, `expired` or `bad_signature`.
+pub fn record_jwt_failure(reason: &str) {
+    metrics().jwt_failures.with_label_values(&[reason]).inc();
+}
+
+/// Runs a bcrypt operation (`hash` or `verify`) and records how long it took.
+pub fn time_bcrypt<T>(op: &str, f: impl FnOnce() -> T) -> T {
+    let timer = metrics().bcrypt_duration.with_label_values(&[op]).start_timer();
+    let result = f();
+    timer.observe_duration();
+    result
+}
+
+/// Middleware that counts every request and records its latency.
+/// Routes are labeled by pattern (`/api/v1/users/{id}`) to keep the label set small.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    // Looked up in the app's route map, so requests a middleware rejects still get their route
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+
+    let result = next.call(req).await;
+
+    let status = match &result {
+        Ok(res) => res.status(),
+        Err(err) => err.as_response_error().status_code(),
+    };
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+
+    let m = metrics();
+    m.http_requests.with_label_values(&labels).inc();
+    m.http_duration
+        .with_label_values(&labels)
+        .observe(started.elapsed().as_secs_f64());
+
+    result
+}
+
+/// Prometheus scrape endpoint in the text exposition format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "ops",
+    responses((status = 200, description = "Metrics in Prometheus text format", content_type = "text/plain"))
+)]
+pub async fn metrics_endpoint(db: web::Data<MySqlPool>) -> impl Responder {
+    let m = metrics();
+
+    // Pool state is read at scrape time rather than tracked on every query.
+    // Active connections at the maximum mean queries are waiting for one.
+    let size = db.size() as i64;
+    let idle = db.num_idle() as i64;
+    m.db_connections.with_label_values(&["idle"]).set(idle);
+    m.db_connections.with_label_values(&["active"]).set(size - idle);
+    m.db_max_connections.set(db.options().get_max_connections() as i64);
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&m.registry.gather(), &mut buffer) {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::BoxBody;
+    use actix_web::error::ErrorUnauthorized;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+
+    async fn reject(_: ServiceRequest, _: Next<BoxBody>) -> Result<ServiceResponse<BoxBody>, Error> {
+        Err(ErrorUnauthorized("Missing token"))
+    }
+
+    #[actix_web::test]
+    async fn rejected_requests_count_under_their_route() {
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(track_requests))
+                .service(web::resource("/metrics-test/{id}").wrap(from_fn(reject)).to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/metrics-test/7").to_request();
+        assert!(test::try_call_service(&app, req).await.is_err());
+
+        let counted = metrics().http_requests.with_label_values(&["GET", "/metrics-test/{id}", "401"]).get();
+        assert_eq!(counted, 1);
+    }
+}
+
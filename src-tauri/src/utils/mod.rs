@@ -1,6 +1,33 @@
+pub mod api_client;
+pub mod api_keys;
 pub mod auth;
 pub mod api_response;
+pub mod app_error;
 pub mod auth_middleware;
+pub mod config;
+pub mod events;
+pub mod files;
 pub mod handlers;
+pub mod health;
+pub mod ldap;
+pub mod metrics;
 pub mod models;
+pub mod mtls;
+pub mod oidc;
+#[cfg(feature = "dev-tools")]
+pub mod oidc_mock;
+pub mod openapi;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod permissions;
+pub mod provisioning;
+pub mod purge;
+pub mod rate_limit;
+pub mod refresh_tokens;
+pub mod security;
 pub mod server;
+pub mod telemetry;
+pub mod tls;
+pub mod user_repo;
+pub mod validation;
+pub mod webhooks;
@@ -1,6 +1,10 @@
-use serde::{Serialize, Deserialize};
+use std::fmt;
 
-#[derive(Debug, Serialize, Deserialize)]
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Serialize, Deserialize, Deserializer};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Serialize, Deserialize)]
 pub struct User {
     pub id: i32,
     pub name: String,
@@ -10,44 +14,315 @@ pub struct User {
 }
 
 
-#[derive(Debug, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct NewUser {
     pub name: String,
     pub email: String,
-    pub role: String,
+    #[serde(default)]
+    pub role: String, // ignored by self-registration, which always creates a `user`
     pub password: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct PublicUser {
     pub id: i32,
     pub name: String,
     pub email: String,
     pub role: String,
+    pub version: i32, // row version, exposed as the ETag
+}
+
+/// A soft-deleted user, as shown in the admin trash listing.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeletedUser {
+    pub id: i32,
+    pub name: String,
+    pub email: String,
+    pub role: String,
+    pub deleted_at: NaiveDateTime, // UTC; purged once older than the retention period
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UpdateUser {
     pub id: i32,
     pub name: String,
     pub email: String,
     pub role: String,
+    #[serde(default, skip_serializing)]
+    pub version: Option<i32>, // expected row version (Tauri only; HTTP uses If-Match)
 }
 
+/// Body of `PUT /api/v1/users/{id}`; the id comes from the path and the version from `If-Match`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReplaceUser {
+    pub name: String,
+    pub email: String,
+    pub role: String,
+}
 
+/// JSON Merge Patch body for a user. Absent fields are left unchanged;
+/// explicit `null`s deserialize to `Some(None)` and are rejected by validation.
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PatchUser {
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub email: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub role: Option<Option<String>>,
+    #[serde(default, skip_serializing)]
+    pub version: Option<i32>, // expected row version (Tauri only; HTTP uses If-Match)
+}
 
-#[derive(Debug, Deserialize)]
+/// Marks a field as present, so `null` can be told apart from a missing key.
+fn present<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+
+
+/// Query parameters of the user change feed.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FeedQuery {
+    /// Alternative to the `Last-Event-ID` header for clients that cannot set headers.
+    pub last_event_id: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
+    #[serde(default, alias = "rememberMe")]
+    pub remember_me: bool, // also issue a refresh token
 }
 
-#[derive(Debug, Serialize)]
+/// A signed-in session; `email` is the account's, which may differ from the login typed.
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct LoginResponse {
     pub token: String,
     pub name: String,
+    pub email: String,
+    pub role: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>, // only when `remember_me` was requested
+}
+
+/// Query string the identity provider redirects the browser back with.
+#[derive(Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// Body of `/auth/oidc/start`, sent by native apps.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OidcStartRequest {
+    /// Loopback URL the app listens on, e.g. `http://127.0.0.1:53124/callback`.
+    pub redirect_uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OidcStartResponse {
+    /// Where the app should open the browser.
+    pub authorization_url: String,
+    pub state: String,
+}
+
+/// Body of `/auth/oidc/exchange`: the code the app's loopback listener received.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct OidcExchangeRequest {
+    pub code: String,
+    pub state: String,
+    #[serde(default, alias = "rememberMe")]
+    pub remember_me: bool, // also issue a refresh token
+}
+
+/// Body of `/auth/refresh` and `/auth/logout`.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// The logged-in identity as returned to the desktop webview (no token).
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub name: String,
+    pub email: String,
     pub role: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A webhook subscription, as shown to admins. The signing secret is only returned on creation.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewWebhook {
+    pub url: String,
+    pub events: Vec<String>,
 }
 
+/// Response to creating a webhook; the only time the secret is shown.
+#[derive(Serialize, ToSchema)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
 
+/// An API key as listed to admins; the key itself is never stored.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    /// Public start of the key, e.g. `mta_3f9c21ab`, for telling keys apart.
+    pub prefix: String,
+    /// Role whose permissions the key acts with.
+    pub role: String,
+    pub scopes: Vec<String>,
+    pub created_by: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewApiKey {
+    pub name: String,
+    pub role: String,
+    pub scopes: Vec<String>,
+    /// UTC; the key never expires if omitted.
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// Response to creating an API key; the only time the key is shown.
+#[derive(Serialize, ToSchema)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+/// An outbox entry: one event queued for one webhook.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i32,
+    pub event: String,
+    pub status: String, // pending, delivered or dead
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub delivered_at: Option<NaiveDateTime>,
+}
 
+/// One HTTP attempt of a delivery, for the delivery log.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeliveryAttempt {
+    pub id: i64,
+    pub delivery_id: i64,
+    pub attempted_at: NaiveDateTime,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+}
+
+// Credentials never show up in `{:?}` output, so they cannot leak into logs.
+const REDACTED: &str = "[REDACTED]";
+
+impl fmt::Debug for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("User")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("email", &self.email)
+            .field("role", &self.role)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
+impl fmt::Debug for NewUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NewUser")
+            .field("name", &self.name)
+            .field("email", &self.email)
+            .field("role", &self.role)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
+impl fmt::Debug for LoginRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoginRequest")
+            .field("email", &self.email)
+            .field("password", &REDACTED)
+            .field("remember_me", &self.remember_me)
+            .finish()
+    }
+}
+
+impl fmt::Debug for OidcExchangeRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OidcExchangeRequest")
+            .field("code", &REDACTED)
+            .field("state", &self.state)
+            .field("remember_me", &self.remember_me)
+            .finish()
+    }
+}
+
+impl fmt::Debug for LoginResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoginResponse")
+            .field("token", &REDACTED)
+            .field("name", &self.name)
+            .field("email", &self.email)
+            .field("role", &self.role)
+            .field("refresh_token", &self.refresh_token.as_ref().map(|_| REDACTED))
+            .finish()
+    }
+}
+
+impl fmt::Debug for RefreshRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RefreshRequest").field("refresh_token", &REDACTED).finish()
+    }
+}
+
+impl fmt::Debug for CreatedApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CreatedApiKey")
+            .field("api_key", &self.api_key)
+            .field("key", &REDACTED)
+            .finish()
+    }
+}
+
+impl fmt::Debug for CreatedWebhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CreatedWebhook")
+            .field("webhook", &self.webhook)
+            .field("secret", &REDACTED)
+            .finish()
+    }
+}
@@ -0,0 +1,181 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::env;
+
+use actix_rt::net::TcpStream;
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::{dev::Extensions, web, HttpRequest};
+use dotenvy::dotenv;
+use x509_parser::error::X509Error;
+
+use crate::utils::auth::Claims;
+use crate::utils::permissions::ROLES;
+
+/// Prefix of the `sub` claim for requests made by a service rather than a user.
+pub const SERVICE_SUBJECT_PREFIX: &str = "service:";
+
+/// The verified client certificate of a connection, captured once per TLS handshake.
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    pub common_name: String,
+    /// Expiry of the certificate as a Unix timestamp.
+    pub not_after: i64,
+}
+
+/// A service authenticated by its client certificate, attached to the request like `Claims`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceIdentity {
+    /// Common name of the certificate subject.
+    pub name: String,
+    pub role: String,
+    pub expires_at: i64,
+}
+
+impl ServiceIdentity {
+    /// Claims for handlers that only look at `Claims`; the subject is `service:<name>`,
+    /// so it never matches a user's email.
+    pub fn claims(&self) -> Claims {
+        Claims {
+            sub: format!("{}{}", SERVICE_SUBJECT_PREFIX, self.name),
+            uid: None,
+            role: self.role.clone(),
+            exp: self.expires_at.max(0) as usize,
+        }
+    }
+}
+
+/// Roles granted to service identities, keyed by certificate common name.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceRoles(HashMap<String, String>);
+
+impl ServiceRoles {
+    /// Reads `MTLS_SERVICE_ROLES`, e.g. `reporting=user,provisioner=admin`.
+    /// An unknown role is a configuration error.
+    pub fn from_env() -> Result<Self, String> {
+        dotenv().ok();
+        Self::parse(&env::var("MTLS_SERVICE_ROLES").unwrap_or_default())
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        let mut roles = HashMap::new();
+        for entry in value.split(',') {
+            let Some((name, role)) = entry.split_once('=') else {
+                continue;
+            };
+            let (name, role) = (name.trim(), role.trim());
+            if !ROLES.contains(&role) {
+                return Err(format!("MTLS_SERVICE_ROLES: invalid role '{}' for service '{}'", role, name));
+            }
+            roles.insert(name.to_string(), role.to_string());
+        }
+        Ok(ServiceRoles(roles))
+    }
+
+    /// The service identity for a verified certificate; a common name without a role is an error.
+    fn identity(&self, cert: &ClientCertificate) -> Result<ServiceIdentity, String> {
+        let role = self
+            .0
+            .get(&cert.common_name)
+            .ok_or_else(|| format!("No service account for certificate '{}'", cert.common_name))?;
+        Ok(ServiceIdentity { name: cert.common_name.clone(), role: role.clone(), expires_at: cert.not_after })
+    }
+}
+
+/// `on_connect` hook that keeps the subject of a verified client certificate with the connection.
+/// rustls has already checked the chain against `TLS_CLIENT_CA_PATH` by the time this runs.
+pub fn capture_client_certificate(conn: &dyn Any, ext: &mut Extensions) {
+    let Some(tls) = conn.downcast_ref::<TlsStream<TcpStream>>() else {
+        return;
+    };
+    let (_, session) = tls.get_ref();
+    let Some(der) = session.peer_certificates().and_then(|certs| certs.first()) else {
+        return;
+    };
+
+    match read_client_certificate(der.as_ref()) {
+        Ok(Some(cert)) => {
+            ext.insert(cert);
+        }
+        Ok(None) => {}
+        Err(e) => tracing::warn!(error = %e, "Unreadable client certificate"),
+    }
+}
+
+// The subject's common name and the expiry of a DER certificate; `None` without a common name.
+fn read_client_certificate(der: &[u8]) -> Result<Option<ClientCertificate>, x509_parser::nom::Err<X509Error>> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der)?;
+    let common_name = cert.subject().iter_common_name().next().and_then(|cn| cn.as_str().ok());
+    Ok(common_name.map(|common_name| ClientCertificate {
+        common_name: common_name.to_string(),
+        not_after: cert.validity().not_after.timestamp(),
+    }))
+}
+
+/// The service identity of the connection, if it presented a client certificate.
+/// A certificate whose common name has no role in `MTLS_SERVICE_ROLES` is an error.
+pub fn service_identity(req: &HttpRequest) -> Result<Option<ServiceIdentity>, String> {
+    let Some(cert) = req.conn_data::<ClientCertificate>() else {
+        return Ok(None);
+    };
+
+    let no_roles = ServiceRoles::default();
+    let roles = req.app_data::<web::Data<ServiceRoles>>().map_or(&no_roles, |roles| roles.get_ref());
+    roles.identity(cert).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, DnType, KeyPair};
+
+    fn certificate(common_name: Option<&str>) -> Vec<u8> {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        if let Some(common_name) = common_name {
+            params.distinguished_name.push(DnType::CommonName, common_name);
+        }
+        let key = KeyPair::generate().unwrap();
+        params.self_signed(&key).unwrap().der().to_vec()
+    }
+
+    #[test]
+    fn roles_are_parsed_per_service() {
+        let roles = ServiceRoles::parse(" reporting = user ,provisioner=admin,").unwrap();
+        assert_eq!(roles.0.get("reporting").map(String::as_str), Some("user"));
+        assert_eq!(roles.0.get("provisioner").map(String::as_str), Some("admin"));
+        assert_eq!(roles.0.len(), 2);
+
+        assert!(ServiceRoles::parse("").unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn unknown_role_is_a_configuration_error() {
+        let err = ServiceRoles::parse("reporting=user,provisioner=root").unwrap_err();
+        assert_eq!(err, "MTLS_SERVICE_ROLES: invalid role 'root' for service 'provisioner'");
+    }
+
+    #[test]
+    fn certificate_subject_maps_to_its_role() {
+        let cert = read_client_certificate(&certificate(Some("reporting"))).unwrap().unwrap();
+        assert_eq!(cert.common_name, "reporting");
+
+        let roles = ServiceRoles::parse("reporting=user").unwrap();
+        let identity = roles.identity(&cert).unwrap();
+        assert_eq!(identity.role, "user");
+        assert_eq!(identity.claims().sub, "service:reporting");
+        assert_eq!(identity.claims().uid, None);
+    }
+
+    #[test]
+    fn certificate_without_a_service_account_is_refused() {
+        let cert = read_client_certificate(&certificate(Some("intruder"))).unwrap().unwrap();
+        let roles = ServiceRoles::parse("reporting=user").unwrap();
+        assert_eq!(roles.identity(&cert).unwrap_err(), "No service account for certificate 'intruder'");
+    }
+
+    #[test]
+    fn certificate_without_a_common_name_is_ignored() {
+        assert!(read_client_certificate(&certificate(None)).unwrap().is_none());
+        assert!(read_client_certificate(b"not a certificate").is_err());
+    }
+}
+
@@ -0,0 +1,481 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::HttpRequest;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use reqwest::Client;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::MySqlPool;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::OnceCell;
+use url::Url;
+
+use crate::utils::app_error::AppError;
+use crate::utils::auth::random_token;
+use crate::utils::config::OidcConfig;
+use crate::utils::models::User;
+use crate::utils::provisioning::{sign_in_external, ExternalUser, ProvisioningPolicy};
+use crate::utils::user_repo::ExternalId;
+
+/// How long a started sign-in may take before its state is forgotten.
+const PENDING_LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Ties a browser sign-in to the browser that started it, so nobody can finish their own
+/// sign-in in someone else's browser (login CSRF).
+pub const STATE_COOKIE: &str = "oidc_state";
+
+/// How long the desktop app waits for the browser to come back to the loopback listener.
+pub const LOOPBACK_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// The parts of the provider's discovery document this client uses.
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    name: Option<String>,
+    nonce: Option<String>,
+    #[serde(flatten)]
+    other: HashMap<String, serde_json::Value>,
+}
+
+/// Who signed in, taken from a verified ID token.
+#[derive(Debug, Clone)]
+pub struct OidcIdentity {
+    pub subject: String,
+    pub email: String,
+    /// Whether the provider vouches for the email (`email_verified: true`).
+    pub email_verified: bool,
+    pub name: String,
+    pub groups: Vec<String>,
+}
+
+/// A sign-in in progress: what has to be kept until the provider redirects back.
+#[derive(Clone)]
+pub struct AuthRequest {
+    /// Where to send the browser.
+    pub url: String,
+    pub state: String,
+    pub nonce: String,
+    pub verifier: String,
+    pub redirect_uri: String,
+}
+
+/// Authorization code flow with PKCE against the configured identity provider.
+pub struct OidcClient {
+    config: OidcConfig,
+    http: Client,
+    metadata: OnceCell<ProviderMetadata>,
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig) -> Self {
+        OidcClient {
+            config,
+            http: Client::new(),
+            metadata: OnceCell::new(),
+        }
+    }
+
+    /// A client for the `OIDC_*` settings, or `None` if single sign-on is not configured.
+    pub fn from_env() -> Option<Self> {
+        OidcConfig::from_env().map(OidcClient::new)
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    // Discovery runs on first use, so the server starts even while the provider is down.
+    async fn metadata(&self) -> Result<&ProviderMetadata, AppError> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", self.config.issuer);
+                let resp = self
+                    .http
+                    .get(&url)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(|e| AppError::internal(format!("OIDC discovery failed: {}", e)))?;
+                resp.json::<ProviderMetadata>()
+                    .await
+                    .map_err(|e| AppError::internal(format!("Invalid OIDC discovery document: {}", e)))
+            })
+            .await
+    }
+
+    /// Starts a sign-in that returns to `redirect_uri`.
+    pub async fn authorization_request(&self, redirect_uri: &str) -> Result<AuthRequest, AppError> {
+        let metadata = self.metadata().await?;
+        let state = random_token(16);
+        let nonce = random_token(16);
+        let verifier = random_token(32);
+        let challenge = pkce_challenge(&verifier);
+
+        let url = Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", self.config.client_id.as_str()),
+                ("redirect_uri", redirect_uri),
+                ("scope", self.config.scopes.as_str()),
+                ("state", state.as_str()),
+                ("nonce", nonce.as_str()),
+                ("code_challenge", challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| AppError::internal(format!("Invalid authorization endpoint: {}", e)))?;
+
+        Ok(AuthRequest {
+            url: url.to_string(),
+            state,
+            nonce,
+            verifier,
+            redirect_uri: redirect_uri.to_string(),
+        })
+    }
+
+    /// Redeems the authorization code and verifies the returned ID token:
+    /// signature against the provider's keys, issuer, audience, expiry and nonce.
+    pub async fn exchange(&self, request: &AuthRequest, code: &str) -> Result<OidcIdentity, AppError> {
+        let metadata = self.metadata().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", request.redirect_uri.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", request.verifier.as_str()),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+
+        let resp = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| AppError::internal(format!("Identity provider unreachable: {}", e)))?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(AppError::unauthorized(format!("Code exchange failed ({}): {}", status, body)));
+        }
+        let tokens: TokenResponse = resp
+            .json()
+            .await
+            .map_err(|e| AppError::internal(format!("Invalid token response: {}", e)))?;
+
+        let claims = self.verify_id_token(metadata, &tokens.id_token).await?;
+        if claims.nonce.as_deref() != Some(request.nonce.as_str()) {
+            return Err(AppError::unauthorized("ID token nonce does not match"));
+        }
+        if claims.email_verified == Some(false) {
+            return Err(AppError::unauthorized("The identity provider has not verified this email address"));
+        }
+        let email = claims
+            .email
+            .ok_or_else(|| AppError::unauthorized("ID token has no email; request the `email` scope"))?;
+
+        let groups = match claims.other.get(&self.config.groups_claim) {
+            Some(serde_json::Value::Array(values)) => {
+                values.iter().filter_map(|v| v.as_str()).map(str::to_string).collect()
+            }
+            Some(serde_json::Value::String(group)) => vec![group.clone()],
+            _ => Vec::new(),
+        };
+
+        Ok(OidcIdentity {
+            subject: claims.sub,
+            name: claims.name.unwrap_or_else(|| email.clone()),
+            email,
+            email_verified: claims.email_verified == Some(true),
+            groups,
+        })
+    }
+
+    // Keys are fetched on every sign-in, so rotations at the provider need no restart.
+    async fn verify_id_token(&self, metadata: &ProviderMetadata, id_token: &str) -> Result<IdTokenClaims, AppError> {
+        let invalid = |e: jsonwebtoken::errors::Error| AppError::unauthorized(format!("Invalid ID token: {}", e));
+
+        let header = decode_header(id_token).map_err(invalid)?;
+        // Symmetric algorithms would mean trusting a key the provider did not publish
+        if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            return Err(AppError::unauthorized("ID tokens signed with a shared secret are not accepted"));
+        }
+
+        let jwks: JwkSet = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| AppError::internal(format!("Could not fetch the provider's keys: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| AppError::internal(format!("Invalid key set: {}", e)))?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None => jwks.keys.first(),
+        }
+        .ok_or_else(|| AppError::unauthorized("ID token was signed with an unknown key"))?;
+        let key = DecodingKey::from_jwk(jwk).map_err(invalid)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(invalid)
+    }
+
+    /// Completes a sign-in: redeems the code and signs the user in.
+    /// Returns the user and whether it was created just now.
+    pub async fn finish(&self, db: &MySqlPool, request: &AuthRequest, code: &str) -> Result<(User, bool), AppError> {
+        let identity = self.exchange(request, code).await?;
+        sign_in(db, &self.config, &identity).await
+    }
+}
+
+// S256 code challenge for a PKCE verifier (RFC 7636).
+fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// Finds or creates the local user for an identity; see `provisioning::sign_in_external`.
+/// Links by email only if `OIDC_LINK_BY_EMAIL` is on and the provider verified the email.
+pub async fn sign_in(db: &MySqlPool, config: &OidcConfig, identity: &OidcIdentity) -> Result<(User, bool), AppError> {
+    let external = ExternalUser {
+        id: ExternalId::OidcSubject(&identity.subject),
+        email: &identity.email,
+        name: &identity.name,
+        mapped_role: config.role_for_groups(&identity.groups),
+    };
+    let policy = ProvisioningPolicy {
+        auto_provision: config.auto_provision,
+        link_by_email: config.link_by_email && identity.email_verified,
+        roles_from_groups: !config.role_mapping.is_empty(),
+        default_role: &config.default_role,
+    };
+    sign_in_external(db, &external, &policy).await
+}
+
+/// Sign-ins started by the server, keyed by their `state`, until the provider redirects back.
+/// Kept in memory, so the callback has to reach the instance that started the sign-in.
+#[derive(Default)]
+pub struct PendingLogins(Mutex<HashMap<String, (AuthRequest, Instant)>>);
+
+impl PendingLogins {
+    pub fn insert(&self, request: AuthRequest) {
+        let mut pending = self.0.lock().unwrap();
+        pending.retain(|_, (_, started)| started.elapsed() < PENDING_LOGIN_TTL);
+        pending.insert(request.state.clone(), (request, Instant::now()));
+    }
+
+    /// Removes and returns the sign-in with the given state, unless it has expired.
+    pub fn take(&self, state: &str) -> Option<AuthRequest> {
+        let (request, started) = self.0.lock().unwrap().remove(state)?;
+        (started.elapsed() < PENDING_LOGIN_TTL).then_some(request)
+    }
+}
+
+/// The cookie carrying a browser sign-in's `state`; it lives as long as the pending sign-in.
+/// `SameSite=Lax` still sends it on the provider's top-level redirect back.
+pub fn state_cookie(state: &str, secure: bool) -> Cookie<'static> {
+    Cookie::build(STATE_COOKIE, state.to_string())
+        .path("/auth/oidc")
+        .http_only(true)
+        .secure(secure)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::seconds(PENDING_LOGIN_TTL.as_secs() as i64))
+        .finish()
+}
+
+/// Whether the browser sending `req` is the one that started the sign-in with `state`.
+pub fn started_in_this_browser(req: &HttpRequest, state: &str) -> bool {
+    req.cookie(STATE_COOKIE).is_some_and(|cookie| cookie.value() == state)
+}
+
+/// Whether `uri` points at this machine over plain HTTP, as native apps' redirects do (RFC 8252).
+pub fn is_loopback_redirect(uri: &str) -> bool {
+    Url::parse(uri).is_ok_and(|url| {
+        url.scheme() == "http" && matches!(url.host_str(), Some("127.0.0.1") | Some("localhost") | Some("[::1]"))
+    })
+}
+
+/// Waits for the browser to come back to the loopback listener and returns the authorization code.
+/// Answers the browser with a short page telling the user to return to the app.
+pub async fn receive_callback(listener: TcpListener, expected_state: &str) -> Result<String, AppError> {
+    tokio::time::timeout(LOOPBACK_TIMEOUT, async {
+        loop {
+            let (mut stream, _) = listener.accept().await.map_err(AppError::internal)?;
+            let mut buf = vec![0u8; 8192];
+            let n = stream.read(&mut buf).await.map_err(AppError::internal)?;
+            let head = String::from_utf8_lossy(&buf[..n]);
+
+            // Request line: "GET /callback?code=...&state=... HTTP/1.1"
+            let target = head.lines().next().and_then(|line| line.split_whitespace().nth(1)).unwrap_or("/");
+            let url = match Url::parse(&format!("http://127.0.0.1{}", target)) {
+                Ok(url) if url.path() == "/callback" => url,
+                // e.g. the browser asking for /favicon.ico
+                _ => {
+                    let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+                    continue;
+                }
+            };
+            let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+
+            let result = if let Some(error) = params.get("error") {
+                Err(AppError::unauthorized(format!(
+                    "Sign-in failed: {}",
+                    params.get("error_description").unwrap_or(error)
+                )))
+            } else if params.get("state").map(String::as_str) != Some(expected_state) {
+                Err(AppError::bad_request("Sign-in state does not match"))
+            } else {
+                params
+                    .get("code")
+                    .cloned()
+                    .ok_or_else(|| AppError::bad_request("The identity provider sent no code"))
+            };
+
+            let message = match &result {
+                Ok(_) => "Signed in. You can close this window and return to the app.",
+                Err(_) => "Sign-in failed. You can close this window and try again in the app.",
+            };
+            let page = format!("<!doctype html><title>Sign-in</title><p>{}</p>", message);
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                page.len(),
+                page
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+            return result;
+        }
+    })
+    .await
+    .map_err(|_| AppError::unauthorized("Timed out waiting for the sign-in to finish"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth_request(state: &str) -> AuthRequest {
+        AuthRequest {
+            url: "https://idp.example.com/authorize".to_string(),
+            state: state.to_string(),
+            nonce: "nonce".to_string(),
+            verifier: "verifier".to_string(),
+            redirect_uri: "http://127.0.0.1:5000/callback".to_string(),
+        }
+    }
+
+    // Sends a browser-like request to the loopback listener and returns the code it yields.
+    async fn callback(path: &str, expected_state: &str) -> Result<String, AppError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr);
+        tokio::spawn(async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let _ = stream.read_to_end(&mut Vec::new()).await;
+        });
+        receive_callback(listener, expected_state).await
+    }
+
+    #[test]
+    fn pkce_challenge_matches_rfc_7636() {
+        // Appendix B of RFC 7636
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn pending_login_is_taken_once() {
+        let pending = PendingLogins::default();
+        pending.insert(auth_request("abc"));
+
+        assert!(pending.take("other").is_none());
+        assert_eq!(pending.take("abc").map(|r| r.nonce), Some("nonce".to_string()));
+        assert!(pending.take("abc").is_none());
+    }
+
+    #[tokio::test]
+    async fn callback_with_matching_state_returns_code() {
+        assert_eq!(callback("/callback?code=xyz&state=abc", "abc").await.unwrap(), "xyz");
+    }
+
+    #[tokio::test]
+    async fn callback_with_other_state_is_rejected() {
+        assert!(callback("/callback?code=xyz&state=forged", "abc").await.is_err());
+        assert!(callback("/callback?code=xyz", "abc").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn callback_with_provider_error_is_rejected() {
+        assert!(callback("/callback?error=access_denied&state=abc", "abc").await.is_err());
+    }
+
+    #[test]
+    fn loopback_redirects() {
+        assert!(is_loopback_redirect("http://127.0.0.1:53124/callback"));
+        assert!(is_loopback_redirect("http://localhost/callback"));
+        assert!(is_loopback_redirect("http://[::1]:8080/callback"));
+    }
+
+    #[test]
+    fn other_redirects_are_not_loopback() {
+        assert!(!is_loopback_redirect("https://127.0.0.1/callback"));
+        assert!(!is_loopback_redirect("http://example.com/callback"));
+        assert!(!is_loopback_redirect("http://127.0.0.1.example.com/callback"));
+        assert!(!is_loopback_redirect("http://localhost@example.com/callback"));
+        assert!(!is_loopback_redirect("/callback"));
+    }
+
+    #[test]
+    fn state_cookie_is_http_only_and_lax() {
+        let cookie = state_cookie("abc", true);
+        assert_eq!(cookie.name(), STATE_COOKIE);
+        assert_eq!(cookie.value(), "abc");
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+        assert_eq!(cookie.path(), Some("/auth/oidc"));
+    }
+
+    #[test]
+    fn callback_must_come_from_the_starting_browser() {
+        let with_cookie = actix_web::test::TestRequest::default().cookie(state_cookie("abc", false)).to_http_request();
+        assert!(started_in_this_browser(&with_cookie, "abc"));
+        assert!(!started_in_this_browser(&with_cookie, "xyz"));
+
+        let without_cookie = actix_web::test::TestRequest::default().to_http_request();
+        assert!(!started_in_this_browser(&without_cookie, "abc"));
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use actix_web::http::header::LOCATION;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use rcgen::KeyPair;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::utils::auth::random_token;
+
+/// Key id of the mock's only signing key.
+const KEY_ID: &str = "mock";
+
+/// Lifetime of the ID tokens the mock issues, in seconds.
+const ID_TOKEN_TTL_SECS: i64 = 300;
+
+/// Who every sign-in at the mock authenticates as, unless the client sends a `login_hint`.
+pub struct MockUser {
+    pub email: String,
+    pub name: String,
+    pub groups: Vec<String>,
+}
+
+// An issued authorization code and what it was issued for
+struct IssuedCode {
+    client_id: String,
+    redirect_uri: String,
+    challenge: String,
+    nonce: Option<String>,
+    email: String,
+}
+
+struct MockProvider {
+    issuer: String,
+    user: MockUser,
+    signing_key: EncodingKey,
+    jwk: serde_json::Value,
+    codes: Mutex<HashMap<String, IssuedCode>>,
+}
+
+#[derive(Deserialize)]
+struct AuthorizeQuery {
+    response_type: String,
+    client_id: String,
+    redirect_uri: String,
+    state: Option<String>,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+    login_hint: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenForm {
+    grant_type: String,
+    code: String,
+    redirect_uri: String,
+    client_id: String,
+    code_verifier: String,
+}
+
+/// Runs a local OpenID provider for trying single sign-on without a real one.
+/// Every authorization request is approved at once for `user`; PKCE (S256) is required
+/// and ID tokens are signed with an ES256 key generated at startup.
+pub async fn run(addr: &str, user: MockUser) -> std::io::Result<()> {
+    let key_pair = KeyPair::generate().map_err(std::io::Error::other)?;
+    let signing_key = EncodingKey::from_ec_pem(key_pair.serialize_pem().as_bytes()).map_err(std::io::Error::other)?;
+    // Uncompressed P-256 point: 0x04, then x and y
+    let point = key_pair.public_key_raw();
+    let jwk = json!({
+        "kty": "EC",
+        "crv": "P-256",
+        "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+        "y": URL_SAFE_NO_PAD.encode(&point[33..65]),
+        "kid": KEY_ID,
+        "alg": "ES256",
+        "use": "sig",
+    });
+
+    let issuer = format!("http://{}", addr);
+    tracing::info!(%issuer, "Mock OIDC provider listening");
+    tracing::info!(email = %user.email, groups = %user.groups.join(", "), "Signing everyone in");
+
+    let provider = web::Data::new(MockProvider {
+        issuer,
+        user,
+        signing_key,
+        jwk,
+        codes: Mutex::new(HashMap::new()),
+    });
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(provider.clone())
+            .route("/.well-known/openid-configuration", web::get().to(discovery))
+            .route("/authorize", web::get().to(authorize))
+            .route("/token", web::post().to(token))
+            .route("/jwks", web::get().to(jwks))
+    })
+    .bind(addr)?
+    .run()
+    .await
+}
+
+async fn discovery(provider: web::Data<MockProvider>) -> HttpResponse {
+    let issuer = &provider.issuer;
+    HttpResponse::Ok().json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": format!("{}/token", issuer),
+        "jwks_uri": format!("{}/jwks", issuer),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["ES256"],
+        "code_challenge_methods_supported": ["S256"],
+    }))
+}
+
+async fn authorize(provider: web::Data<MockProvider>, query: web::Query<AuthorizeQuery>) -> HttpResponse {
+    let query = query.into_inner();
+    if query.response_type != "code" {
+        return HttpResponse::BadRequest().body("Only response_type=code is supported");
+    }
+    let Some(challenge) = query.code_challenge.filter(|_| query.code_challenge_method.as_deref() == Some("S256")) else {
+        return HttpResponse::BadRequest().body("PKCE with code_challenge_method=S256 is required");
+    };
+    let Ok(mut redirect) = Url::parse(&query.redirect_uri) else {
+        return HttpResponse::BadRequest().body("Invalid redirect_uri");
+    };
+
+    let email = query.login_hint.unwrap_or_else(|| provider.user.email.clone());
+    let code = random_token(16);
+    tracing::info!(%email, client_id = %query.client_id, "Approved sign-in");
+    provider.codes.lock().unwrap().insert(
+        code.clone(),
+        IssuedCode {
+            client_id: query.client_id,
+            redirect_uri: query.redirect_uri,
+            challenge,
+            nonce: query.nonce,
+            email,
+        },
+    );
+
+    {
+        let mut params = redirect.query_pairs_mut();
+        params.append_pair("code", &code);
+        if let Some(state) = &query.state {
+            params.append_pair("state", state);
+        }
+    }
+    HttpResponse::Found().insert_header((LOCATION, redirect.to_string())).finish()
+}
+
+async fn token(provider: web::Data<MockProvider>, form: web::Form<TokenForm>) -> HttpResponse {
+    let invalid_grant = |description: &str| {
+        tracing::info!(reason = description, "Rejected code exchange");
+        HttpResponse::BadRequest().json(json!({ "error": "invalid_grant", "error_description": description }))
+    };
+
+    if form.grant_type != "authorization_code" {
+        return HttpResponse::BadRequest().json(json!({ "error": "unsupported_grant_type" }));
+    }
+    // Codes are single use, even when the exchange fails
+    let Some(issued) = provider.codes.lock().unwrap().remove(&form.code) else {
+        return invalid_grant("unknown or used code");
+    };
+    if issued.client_id != form.client_id || issued.redirect_uri != form.redirect_uri {
+        return invalid_grant("client_id or redirect_uri does not match the authorization request");
+    }
+    if URL_SAFE_NO_PAD.encode(Sha256::digest(form.code_verifier.as_bytes())) != issued.challenge {
+        return invalid_grant("code_verifier does not match the code_challenge");
+    }
+
+    let now = Utc::now().timestamp();
+    // A stable subject per email, so repeated sign-ins resolve to the same linked account
+    let subject = hex::encode(&Sha256::digest(issued.email.as_bytes())[..12]);
+    let name = if issued.email == provider.user.email {
+        provider.user.name.clone()
+    } else {
+        issued.email.clone()
+    };
+    let claims = json!({
+        "iss": provider.issuer,
+        "sub": subject,
+        "aud": issued.client_id,
+        "iat": now,
+        "exp": now + ID_TOKEN_TTL_SECS,
+        "nonce": issued.nonce,
+        "email": issued.email,
+        "email_verified": true,
+        "name": name,
+        "groups": provider.user.groups,
+    });
+
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = Some(KEY_ID.to_string());
+    let id_token = match encode(&header, &claims, &provider.signing_key) {
+        Ok(token) => token,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+
+    tracing::info!(email = %issued.email, %subject, "Issued ID token");
+    HttpResponse::Ok().json(json!({
+        "access_token": random_token(16),
+        "token_type": "Bearer",
+        "expires_in": ID_TOKEN_TTL_SECS,
+        "id_token": id_token,
+    }))
+}
+
+async fn jwks(provider: web::Data<MockProvider>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "keys": [provider.jwk] }))
+}
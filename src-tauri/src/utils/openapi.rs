@@ -0,0 +1,131 @@
+use utoipa::openapi::security::{ApiKey as ApiKeyScheme, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::Deprecated;
+use utoipa::{Modify, OpenApi};
+
+use crate::utils::app_error::AppError;
+use crate::utils::api_keys::API_KEY_HEADER;
+use crate::utils::auth::Claims;
+use crate::utils::handlers;
+use crate::utils::health::{self, BuildInfo, Readiness};
+use crate::utils::metrics;
+use crate::utils::models::{
+    ApiKey, CreatedApiKey, CreatedWebhook, DeletedUser, DeliveryAttempt, LoginRequest, LoginResponse, NewApiKey, NewUser,
+    NewWebhook, OidcExchangeRequest, OidcStartRequest, OidcStartResponse, PatchUser, PublicUser,
+    RefreshRequest, ReplaceUser, UpdateUser, Webhook, WebhookDelivery,
+};
+
+/// OpenAPI document of the REST API, built from the `#[utoipa::path]` annotations on the handlers.
+/// Served at `/openapi.json`; a route without an entry here is missing from the docs.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "User management API"),
+    paths(
+        handlers::register,
+        handlers::login,
+        handlers::refresh,
+        handlers::logout,
+        handlers::oidc_login,
+        handlers::oidc_callback,
+        handlers::oidc_start,
+        handlers::oidc_exchange,
+        handlers::protected,
+        handlers::fetch_all_users,
+        handlers::create_user_v1,
+        handlers::user_events,
+        handlers::get_user,
+        handlers::replace_user_v1,
+        handlers::patch_user,
+        handlers::delete_user_v1,
+        handlers::fetch_deleted_users,
+        handlers::restore_user,
+        handlers::create_webhook,
+        handlers::fetch_webhooks,
+        handlers::delete_webhook,
+        handlers::fetch_webhook_deliveries,
+        handlers::fetch_delivery_attempts,
+        handlers::retry_webhook_delivery,
+        handlers::create_api_key,
+        handlers::fetch_api_keys,
+        handlers::revoke_api_key,
+        handlers::create_user,
+        handlers::update_user,
+        handlers::delete_user,
+        health::healthz,
+        health::readyz,
+        health::version,
+        metrics::metrics_endpoint,
+    ),
+    components(schemas(
+        AppError,
+        Claims,
+        NewUser,
+        PublicUser,
+        DeletedUser,
+        UpdateUser,
+        ReplaceUser,
+        PatchUser,
+        LoginRequest,
+        LoginResponse,
+        RefreshRequest,
+        OidcStartRequest,
+        OidcStartResponse,
+        OidcExchangeRequest,
+        Webhook,
+        NewWebhook,
+        CreatedWebhook,
+        WebhookDelivery,
+        DeliveryAttempt,
+        ApiKey,
+        NewApiKey,
+        CreatedApiKey,
+        Readiness,
+        BuildInfo,
+    )),
+    modifiers(&BearerAuth, &DeprecatedAliases),
+    tags(
+        (name = "auth", description = "Registration, login and sessions"),
+        (name = "users", description = "User management"),
+        (name = "webhooks", description = "Webhook subscriptions and deliveries (admins only)"),
+        (name = "api-keys", description = "API keys for scripts (admins only)"),
+        (name = "ops", description = "Health, readiness, build information and metrics"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Declares the `bearer` scheme referenced by the handlers' `security` attributes,
+/// and the `api_key` header that scripts may use instead.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "bearer",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build()),
+            );
+            components.add_security_scheme(
+                "api_key",
+                SecurityScheme::ApiKey(ApiKeyScheme::Header(ApiKeyValue::with_description(
+                    API_KEY_HEADER,
+                    "An `mta_` key, also accepted as `Authorization: ApiKey <key>`. Reads need the `users:read` scope, \
+                     other requests `users:write`, and `/api/webhooks` needs `webhooks:manage`.",
+                ))),
+            );
+        }
+    }
+}
+
+/// Marks the pre-v1 `/api/users/...` routes as deprecated.
+struct DeprecatedAliases;
+
+impl Modify for DeprecatedAliases {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if path.starts_with("/api/users/") {
+                for operation in item.operations.values_mut() {
+                    operation.deprecated = Some(Deprecated::True);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::env;
+
+use actix_web::{http::header::HeaderMap, web, App, HttpResponse, HttpServer};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TraceError;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_proto::tonic::collector::trace::v1::{ExportTraceServiceRequest, ExportTraceServiceResponse};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::{runtime, trace as sdktrace, Resource};
+use prost::Message;
+use tracing::Span;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Export batches from the collector stub can be large; the default payload limit is 256 KiB.
+const STUB_PAYLOAD_LIMIT: usize = 16 * 1024 * 1024;
+
+/// The layer exporting spans over OTLP/HTTP, or `None` when neither
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` nor `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` is set.
+pub fn layer<S>() -> Option<OpenTelemetryLayer<S, sdktrace::Tracer>>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    if env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_none()
+        && env::var_os("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").is_none()
+    {
+        return None;
+    }
+
+    match tracer() {
+        Ok(tracer) => Some(tracing_opentelemetry::layer().with_tracer(tracer)),
+        Err(e) => {
+            // The subscriber is not installed yet, so this cannot go through tracing
+            eprintln!("Trace export disabled: {}", e);
+            None
+        }
+    }
+}
+
+// The exporter reads the endpoint, headers and timeout from the standard OTEL_EXPORTER_OTLP_* variables.
+fn tracer() -> Result<sdktrace::Tracer, TraceError> {
+    let service_name = env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| env!("CARGO_PKG_NAME").to_string());
+
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().http())
+        .with_trace_config(sdktrace::config().with_resource(Resource::new([
+            KeyValue::new("service.name", service_name),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        ])))
+        .install_batch(runtime::Tokio)
+}
+
+/// Flushes pending spans. The provider blocks while flushing, so it runs off the async workers.
+pub async fn shutdown() {
+    let _ = tokio::task::spawn_blocking(global::shutdown_tracer_provider).await;
+}
+
+/// Makes the trace in the request's `traceparent` (and `tracestate`) the parent of `span`.
+/// Without a valid header the span starts a new trace.
+pub fn set_parent(span: &Span, headers: &HeaderMap) {
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    span.set_parent(parent);
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Runs a local OTLP/HTTP receiver that prints every exported span, one line each,
+/// so the export can be checked without a real collector.
+pub async fn run_collector_stub(addr: &str) -> std::io::Result<()> {
+    println!("OTLP collector stub listening on http://{}/v1/traces", addr);
+
+    HttpServer::new(|| {
+        App::new()
+            .app_data(web::PayloadConfig::new(STUB_PAYLOAD_LIMIT))
+            .route("/v1/traces", web::post().to(receive_traces))
+    })
+    .bind(addr)?
+    .run()
+    .await
+}
+
+async fn receive_traces(body: web::Bytes) -> HttpResponse {
+    let request = match ExportTraceServiceRequest::decode(body) {
+        Ok(request) => request,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    for resource_spans in request.resource_spans {
+        for scope_spans in resource_spans.scope_spans {
+            for span in scope_spans.spans {
+                let parent = if span.parent_span_id.is_empty() {
+                    "root".to_string()
+                } else {
+                    hex::encode(&span.parent_span_id)
+                };
+                let duration_ms = span.end_time_unix_nano.saturating_sub(span.start_time_unix_nano) as f64 / 1e6;
+                println!(
+                    "trace {} span {} parent {:<16} {:>10.3}ms  {}",
+                    hex::encode(&span.trace_id),
+                    hex::encode(&span.span_id),
+                    parent,
+                    duration_ms,
+                    span.name
+                );
+            }
+        }
+    }
+
+    HttpResponse::Ok()
+        .content_type("application/x-protobuf")
+        .body(ExportTraceServiceResponse::default().encode_to_vec())
+}
@@ -0,0 +1,79 @@
+use crate::utils::app_error::AppError;
+use crate::utils::models::{PatchUser, PublicUser};
+
+/// Roles a user can be assigned.
+pub const ROLES: [&str; 3] = ["admin", "manager", "user"];
+
+/// Role of accounts created through public self-registration.
+pub const REGISTERED_ROLE: &str = "user";
+
+/// Actions that are granted per role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Edit another user's name and email.
+    EditUsers,
+    /// Change a user's role.
+    ChangeRoles,
+}
+
+/// Returns whether the given role is granted the permission.
+pub fn has_permission(role: &str, permission: Permission) -> bool {
+    match role {
+        "admin" => true,
+        "manager" => permission == Permission::EditUsers,
+        _ => false,
+    }
+}
+
+/// Returns whether a change to `user` may be shown to the caller.
+/// Roles that can edit users see every change; everyone else only changes to their own account.
+pub fn can_view_user_changes(role: &str, caller_email: &str, user: &PublicUser) -> bool {
+    has_permission(role, Permission::EditUsers) || user.email == caller_email
+}
+
+/// Checks that the caller's role may change every field present in the patch.
+pub fn authorize_patch(role: &str, patch: &PatchUser) -> Result<(), AppError> {
+    if (patch.name.is_some() || patch.email.is_some()) && !has_permission(role, Permission::EditUsers) {
+        return Err(AppError::forbidden("Access denied: you cannot edit users"));
+    }
+    if patch.role.is_some() && !has_permission(role, Permission::ChangeRoles) {
+        return Err(AppError::forbidden("Access denied: only admins can change roles"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(name: bool, role: bool) -> PatchUser {
+        PatchUser {
+            name: name.then(|| Some("Ada".to_string())),
+            role: role.then(|| Some("manager".to_string())),
+            ..PatchUser::default()
+        }
+    }
+
+    #[test]
+    fn users_cannot_edit_anyone() {
+        assert!(authorize_patch("user", &patch(true, false)).is_err());
+        assert!(authorize_patch("user", &patch(false, true)).is_err());
+    }
+
+    #[test]
+    fn managers_can_edit_details_but_not_roles() {
+        assert!(authorize_patch("manager", &patch(true, false)).is_ok());
+        assert!(authorize_patch("manager", &patch(false, true)).is_err());
+        assert!(authorize_patch("manager", &patch(true, true)).is_err());
+    }
+
+    #[test]
+    fn admins_can_change_everything() {
+        assert!(authorize_patch("admin", &patch(true, true)).is_ok());
+    }
+
+    #[test]
+    fn empty_patch_needs_no_permission() {
+        assert!(authorize_patch("user", &PatchUser::default()).is_ok());
+    }
+}
@@ -0,0 +1,140 @@
+use sqlx::MySqlPool;
+
+use crate::utils::app_error::AppError;
+use crate::utils::auth::unusable_password_hash;
+use crate::utils::models::{NewUser, User};
+use crate::utils::user_repo::{self, ExternalId};
+
+/// A user who just proved who they are to single sign-on or the directory.
+pub struct ExternalUser<'a> {
+    pub id: ExternalId<'a>,
+    pub email: &'a str,
+    pub name: &'a str,
+    /// Role granted by the user's mapped groups, if any.
+    pub mapped_role: Option<String>,
+}
+
+/// What the identity source's settings allow when an unknown account signs in.
+pub struct ProvisioningPolicy<'a> {
+    /// Create a local user for an account that has none.
+    pub auto_provision: bool,
+    /// Link an unlinked local user with the same email.
+    pub link_by_email: bool,
+    /// Whether groups are mapped to roles; the source then decides the role on every sign-in.
+    pub roles_from_groups: bool,
+    /// Role of users that are in none of the mapped groups.
+    pub default_role: &'a str,
+}
+
+/// Finds the local user for an external account: by link first, then by email if the policy
+/// allows it, linking it. Unknown users are created and linked in one transaction if
+/// auto-provisioning is on. With group mapping, the role follows the groups within the admin guardrails.
+/// Returns the user and whether it was created just now.
+pub async fn sign_in_external(
+    db: &MySqlPool,
+    external: &ExternalUser<'_>,
+    policy: &ProvisioningPolicy<'_>,
+) -> Result<(User, bool), AppError> {
+    let (linked_to, source) = match external.id {
+        ExternalId::OidcSubject(_) => ("single sign-on identity", "single sign-on"),
+        ExternalId::LdapDn(_) => ("directory entry", "the directory"),
+    };
+
+    let (mut user, created) = match user_repo::find_user_by_external_id(db, external.id).await? {
+        Some(user) => (user, false),
+        None => match user_repo::find_user_by_email(db, external.email).await? {
+            // Whoever controls the email at the source would get the account
+            Some(_) if !policy.link_by_email => {
+                return Err(AppError::forbidden(format!(
+                    "{} already has an account that is not linked to {}",
+                    external.email, source
+                )));
+            }
+            Some(user) => {
+                if !user_repo::link_external_id(db, user.id, external.id).await? {
+                    return Err(AppError::forbidden(format!("This account is linked to another {}", linked_to)));
+                }
+                (user, false)
+            }
+            None if policy.auto_provision => (provision(db, external, policy).await?, true),
+            None => return Err(AppError::forbidden(format!("No account for {}", external.email))),
+        },
+    };
+
+    // The source's groups are authoritative for the role, within the admin guardrails
+    if let Some(role) = source_role(external, policy).filter(|role| *role != user.role) {
+        match user_repo::set_role(db, &user.email, role).await {
+            Ok(Some(_)) => user.role = role.to_string(),
+            Ok(None) => {}
+            Err(e) => tracing::warn!(user = %user.email, error = %e, "Kept role despite group mapping"),
+        }
+    }
+
+    Ok((user, created))
+}
+
+// The role the source's groups give the user, or `None` if groups are not mapped.
+// Someone removed from every mapped group falls back to the default role.
+fn source_role<'a>(external: &'a ExternalUser<'_>, policy: &ProvisioningPolicy<'a>) -> Option<&'a str> {
+    match &external.mapped_role {
+        Some(role) => Some(role),
+        None if policy.roles_from_groups => Some(policy.default_role),
+        None => None,
+    }
+}
+
+// The user signs in through the source, never with a local password.
+async fn provision(db: &MySqlPool, external: &ExternalUser<'_>, policy: &ProvisioningPolicy<'_>) -> Result<User, AppError> {
+    let new_user = NewUser {
+        name: external.name.to_string(),
+        email: external.email.to_string(),
+        role: source_role(external, policy).unwrap_or(policy.default_role).to_string(),
+        password: String::new(),
+    };
+    let hashed = unusable_password_hash().map_err(AppError::internal)?;
+
+    // A user that exists but is not linked could be taken over by the next email match
+    let mut tx = db.begin().await?;
+    let id = user_repo::insert_user_in(&mut tx, &new_user, &hashed).await? as i32;
+    if !user_repo::link_external_id(&mut *tx, id, external.id).await? {
+        return Err(AppError::internal("Could not link the provisioned user"));
+    }
+    tx.commit().await?;
+
+    user_repo::find_user_by_email(db, external.email)
+        .await?
+        .ok_or_else(|| AppError::internal("Provisioned user not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn external(mapped_role: Option<&str>) -> ExternalUser<'static> {
+        ExternalUser {
+            id: ExternalId::LdapDn("uid=ada,ou=people,dc=example,dc=org"),
+            email: "ada@example.org",
+            name: "Ada",
+            mapped_role: mapped_role.map(str::to_string),
+        }
+    }
+
+    fn policy(roles_from_groups: bool) -> ProvisioningPolicy<'static> {
+        ProvisioningPolicy { auto_provision: true, link_by_email: false, roles_from_groups, default_role: "user" }
+    }
+
+    #[test]
+    fn mapped_group_decides_the_role() {
+        assert_eq!(source_role(&external(Some("admin")), &policy(true)), Some("admin"));
+    }
+
+    #[test]
+    fn user_removed_from_every_mapped_group_is_demoted() {
+        assert_eq!(source_role(&external(None), &policy(true)), Some("user"));
+    }
+
+    #[test]
+    fn role_is_left_alone_without_group_mapping() {
+        assert_eq!(source_role(&external(None), &policy(false)), None);
+    }
+}
@@ -0,0 +1,33 @@
+use sqlx::MySqlPool;
+use std::env;
+use std::time::Duration;
+
+use crate::utils::config::interval_from_env;
+use crate::utils::user_repo;
+
+/// Days a soft-deleted user is kept before being purged, unless `USER_RETENTION_DAYS` is set.
+const DEFAULT_RETENTION_DAYS: u32 = 30;
+
+/// Minutes between purge runs, unless `USER_PURGE_INTERVAL_MINUTES` is set.
+const DEFAULT_INTERVAL_MINUTES: u64 = 60;
+
+/// Spawns the background job that permanently removes expired soft-deleted users.
+pub fn spawn_purge_job(pool: MySqlPool) {
+    let retention_days = env::var("USER_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+    let interval_minutes = interval_from_env("USER_PURGE_INTERVAL_MINUTES", DEFAULT_INTERVAL_MINUTES);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_minutes * 60));
+        loop {
+            interval.tick().await;
+            match user_repo::purge_deleted_users(&pool, retention_days).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!(purged = n, retention_days, "Purged deleted users"),
+                Err(e) => tracing::error!(error = %e, "Failed to purge deleted users"),
+            }
+        }
+    });
+}
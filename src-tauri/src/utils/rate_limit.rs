@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::env;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::header::RETRY_AFTER,
+    web, Error, HttpResponse,
+};
+use actix_web::middleware::Next;
+
+/// Requests per client and minute, unless `PROBE_RATE_LIMIT_PER_MINUTE` is set.
+const DEFAULT_PER_MINUTE: u32 = 120;
+
+/// Above this many tracked clients, expired windows are swept on the next request.
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// Fixed-window request limit per client IP, shared by all workers.
+pub struct RateLimiter {
+    max: u32,
+    window: Duration,
+    clients: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    /// Allows `max` requests per client in every `window`.
+    pub fn new(max: u32, window: Duration) -> Self {
+        RateLimiter {
+            max,
+            window,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Limiter for the unauthenticated probe endpoints.
+    pub fn probes_from_env() -> Self {
+        let per_minute = env::var("PROBE_RATE_LIMIT_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_PER_MINUTE);
+        Self::new(per_minute, Duration::from_secs(60))
+    }
+
+    /// Counts a request; returns how long the client has to wait if it is over the limit.
+    fn check(&self, client: IpAddr) -> Option<Duration> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+
+        if clients.len() > SWEEP_THRESHOLD {
+            let window = self.window;
+            clients.retain(|_, (start, _)| now.duration_since(*start) < window);
+        }
+
+        let (start, count) = clients.entry(client).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+
+        if *count >= self.max {
+            return Some(self.window - now.duration_since(*start));
+        }
+        *count += 1;
+        None
+    }
+}
+
+/// Middleware that enforces the `RateLimiter` registered as app data.
+/// Clients are told by peer address; forwarded-for headers are not trusted.
+pub async fn limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+    let client = req.peer_addr().map(|addr| addr.ip());
+
+    if let (Some(limiter), Some(client)) = (limiter, client) {
+        if let Some(wait) = limiter.check(client) {
+            let response = HttpResponse::TooManyRequests()
+                .insert_header((RETRY_AFTER, wait.as_secs().max(1).to_string()))
+                .body("Too many requests");
+            return Err(InternalError::from_response("Too many requests", response).into());
+        }
+    }
+
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn rejects_requests_over_the_limit() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        assert_eq!(limiter.check(ip("10.0.0.1")), None);
+        assert_eq!(limiter.check(ip("10.0.0.1")), None);
+
+        let wait = limiter.check(ip("10.0.0.1")).unwrap();
+        assert!(wait > Duration::from_secs(59) && wait <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn counts_each_client_separately() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        assert_eq!(limiter.check(ip("10.0.0.1")), None);
+        assert!(limiter.check(ip("10.0.0.1")).is_some());
+
+        assert_eq!(limiter.check(ip("10.0.0.2")), None);
+        assert_eq!(limiter.check(ip("::1")), None);
+        assert!(limiter.check(ip("10.0.0.2")).is_some());
+    }
+
+    #[test]
+    fn starts_a_new_window_once_the_old_one_ends() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(50));
+        assert_eq!(limiter.check(ip("10.0.0.1")), None);
+        assert!(limiter.check(ip("10.0.0.1")).is_some());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(limiter.check(ip("10.0.0.1")), None);
+        assert!(limiter.check(ip("10.0.0.1")).is_some());
+    }
+
+    #[actix_web::test]
+    async fn over_limit_answers_429_with_retry_after() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(RateLimiter::new(1, Duration::from_secs(60))))
+                .service(web::resource("/healthz").wrap(from_fn(limit)).to(HttpResponse::Ok)),
+        )
+        .await;
+        let request = || test::TestRequest::get().uri("/healthz").peer_addr("10.0.0.1:4000".parse().unwrap()).to_request();
+
+        let res = test::call_service(&app, request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let err = test::try_call_service(&app, request()).await.err().unwrap();
+        let res = err.error_response();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = res.headers().get(RETRY_AFTER).unwrap().to_str().unwrap().parse().unwrap();
+        assert!((1..=60).contains(&retry_after));
+    }
+}
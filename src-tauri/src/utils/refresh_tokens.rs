@@ -0,0 +1,84 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::MySqlPool;
+
+use crate::utils::models::User;
+
+/// Days a refresh token stays valid after it is issued.
+pub const REFRESH_TOKEN_TTL_DAYS: u32 = 30;
+
+// Only the hash is stored, so a database leak does not hand out sessions.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Issues a new refresh token for the user and returns it in plain text.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn issue(db: &MySqlPool, user_id: i32) -> Result<String, sqlx::Error> {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = URL_SAFE_NO_PAD.encode(bytes);
+
+    sqlx::query!(
+        "INSERT INTO refresh_tokens (user_id, token_hash, expires_at) VALUES (?, ?, UTC_TIMESTAMP() + INTERVAL ? DAY)",
+        user_id,
+        hash_token(&token),
+        REFRESH_TOKEN_TTL_DAYS
+    )
+    .execute(db)
+    .await?;
+
+    Ok(token)
+}
+
+/// Exchanges a valid refresh token for its user and a replacement token.
+/// The presented token is consumed; returns `None` if it is unknown, expired
+/// or belongs to a deleted user.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn redeem(db: &MySqlPool, token: &str) -> Result<Option<(User, String)>, sqlx::Error> {
+    let token_hash = hash_token(token);
+    let mut tx = db.begin().await?;
+
+    let user = sqlx::query_as!(
+        User,
+        "SELECT u.id, u.name, u.email, u.role, u.password FROM refresh_tokens r JOIN users u ON u.id = r.user_id WHERE r.token_hash = ? AND r.expires_at > UTC_TIMESTAMP() AND u.deleted_at IS NULL FOR UPDATE",
+        token_hash
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM refresh_tokens WHERE token_hash = ?", token_hash)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    match user {
+        Some(u) => {
+            let replacement = issue(db, u.id).await?;
+            Ok(Some((u, replacement)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Revokes a single refresh token, e.g. on logout.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn revoke(db: &MySqlPool, token: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM refresh_tokens WHERE token_hash = ?", hash_token(token))
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Revokes every refresh token of a user, e.g. after a password change.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn revoke_all_for_email(db: &MySqlPool, email: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE r FROM refresh_tokens r JOIN users u ON u.id = r.user_id WHERE u.email = ?",
+        email
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
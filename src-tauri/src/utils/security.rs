@@ -0,0 +1,179 @@
+use actix_cors::Cors;
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{
+        HeaderValue, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+    },
+    Error,
+};
+use actix_web::middleware::Next;
+
+use crate::utils::config::CorsConfig;
+
+/// Headers a browser client may send with a cross-origin request.
+const ALLOWED_HEADERS: [&str; 7] = [
+    "authorization",
+    "x-api-key",
+    "content-type",
+    "if-match",
+    "last-event-id",
+    "x-request-id",
+    "traceparent",
+];
+
+/// Response headers a browser client may read; CORS hides everything else.
+const EXPOSED_HEADERS: [&str; 7] =
+    ["etag", "location", "link", "deprecation", "sunset", "retry-after", "x-request-id"];
+
+/// Builds the CORS middleware from the configured origin list.
+/// Requests from other origins get no CORS headers, so browsers block them.
+pub fn cors(config: &CorsConfig) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(["GET", "POST", "PUT", "PATCH", "DELETE"])
+        .allowed_headers(ALLOWED_HEADERS)
+        .expose_headers(EXPOSED_HEADERS)
+        .max_age(config.max_age_secs);
+
+    let any_origin = config.allowed_origins.iter().any(|origin| origin == "*");
+    if any_origin {
+        cors = cors.allow_any_origin();
+    } else {
+        for origin in &config.allowed_origins {
+            cors = cors.allowed_origin(origin);
+        }
+    }
+
+    // Credentials for any origin would let every site act as the user
+    if config.allow_credentials && !any_origin {
+        cors = cors.supports_credentials();
+    }
+    cors
+}
+
+/// Middleware adding HSTS, `nosniff`, frame and referrer headers to every response,
+/// including the errors returned by inner middleware such as the auth check.
+pub async fn security_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let http_req = req.request().clone();
+    let mut res = match next.call(req).await {
+        Ok(res) => res.map_into_boxed_body(),
+        Err(err) => ServiceResponse::from_err(err, http_req),
+    };
+
+    let headers = res.headers_mut();
+    // Browsers only honour HSTS over HTTPS, so it is harmless on plain HTTP
+    headers.insert(STRICT_TRANSPORT_SECURITY, HeaderValue::from_static("max-age=31536000; includeSubDomains"));
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+    headers.insert(REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::error::ErrorUnauthorized;
+    use actix_web::http::header::{ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_ORIGIN, ORIGIN};
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, web, App, HttpResponse};
+
+    const APP_ORIGIN: &str = "https://app.example.com";
+
+    fn config(origins: &[&str]) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.iter().map(|o| o.to_string()).collect(),
+            allow_credentials: true,
+            max_age_secs: 600,
+        }
+    }
+
+    async fn cors_app(
+        origins: &[&str],
+    ) -> impl actix_web::dev::Service<actix_http::Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+        test::init_service(App::new().wrap(cors(&config(origins))).route("/", web::get().to(HttpResponse::Ok))).await
+    }
+
+    async fn reject(_: ServiceRequest, _: Next<BoxBody>) -> Result<ServiceResponse<BoxBody>, Error> {
+        Err(ErrorUnauthorized("Missing token"))
+    }
+
+    fn assert_security_headers(res: &ServiceResponse<impl MessageBody>) {
+        let headers = res.headers();
+        assert_eq!(headers.get(STRICT_TRANSPORT_SECURITY).unwrap(), "max-age=31536000; includeSubDomains");
+        assert_eq!(headers.get(X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+        assert_eq!(headers.get(X_FRAME_OPTIONS).unwrap(), "DENY");
+        assert_eq!(headers.get(REFERRER_POLICY).unwrap(), "no-referrer");
+    }
+
+    #[actix_web::test]
+    async fn allowed_origin_gets_cors_headers() {
+        let app = cors_app(&[APP_ORIGIN]).await;
+
+        let req = test::TestRequest::get().uri("/").insert_header((ORIGIN, APP_ORIGIN)).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), APP_ORIGIN);
+        assert_eq!(res.headers().get(ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+    }
+
+    #[actix_web::test]
+    async fn disallowed_origin_is_rejected() {
+        let app = cors_app(&[APP_ORIGIN]).await;
+
+        let req = test::TestRequest::get().uri("/").insert_header((ORIGIN, "https://evil.example.com")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(res.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        let preflight = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .insert_header((ORIGIN, "https://evil.example.com"))
+            .insert_header(("access-control-request-method", "DELETE"))
+            .to_request();
+        let res = test::call_service(&app, preflight).await;
+        assert!(!res.status().is_success());
+        assert!(res.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[actix_web::test]
+    async fn any_origin_never_allows_credentials() {
+        let app = cors_app(&["*"]).await;
+
+        let req = test::TestRequest::get().uri("/").insert_header((ORIGIN, "https://other.example.com")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_some());
+        assert!(res.headers().get(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+    }
+
+    #[actix_web::test]
+    async fn security_headers_are_added_to_responses() {
+        let app = test::init_service(
+            App::new().wrap(from_fn(security_headers)).route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_security_headers(&res);
+    }
+
+    #[actix_web::test]
+    async fn security_headers_are_added_to_errors() {
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(security_headers))
+                .service(web::resource("/").wrap(from_fn(reject)).to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_security_headers(&res);
+    }
+}
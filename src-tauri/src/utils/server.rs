@@ -1,13 +1,40 @@
 ﻿use actix_web::{web, HttpServer, App, HttpResponse};
-use actix_web::middleware::{Logger, from_fn};
+use actix_web::dev::Server;
+use actix_web::middleware::{Condition, DefaultHeaders, from_fn};
+use actix_web::http::header::LINK;
 use sqlx::MySqlPool;
+use sqlx::migrate::Migrator;
 use dotenvy::dotenv;
 use std::env;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
 use crate::utils::auth_middleware;
-use crate::utils::handlers::{register, login, protected, create_user, fetch_all_users, update_user, delete_user};
-use std::time::Duration;
+use crate::utils::openapi::ApiDoc;
+use crate::utils::config::{server_addr, CorsConfig, LdapConfig, TlsConfig};
+use crate::utils::events::EventBus;
+use crate::utils::purge::spawn_purge_job;
+use crate::utils::health::{healthz, readyz, version};
+use crate::utils::rate_limit::{self, RateLimiter};
+use crate::utils::metrics::{self, metrics_endpoint};
+use crate::utils::mtls::{self, ServiceRoles};
+use crate::utils::oidc::{OidcClient, PendingLogins};
+use crate::utils::security;
+use crate::utils::telemetry;
+use crate::utils::tls::{self, HttpsPort};
+use crate::utils::webhooks::spawn_webhook_jobs;
+use crate::utils::handlers::{register, login, refresh, logout, protected, create_user, fetch_all_users, get_user, update_user, patch_user, delete_user, fetch_deleted_users, restore_user, user_events, create_user_v1, replace_user_v1, delete_user_v1, create_webhook, fetch_webhooks, delete_webhook, fetch_webhook_deliveries, fetch_delivery_attempts, retry_webhook_delivery, create_api_key, fetch_api_keys, revoke_api_key, oidc_login, oidc_callback, oidc_start, oidc_exchange};
 
-pub async fn run_server() -> MySqlPool {
+/// When the pre-v1 `/api/users` routes were deprecated, as an RFC 9745 `Deprecation` date.
+const USERS_DEPRECATED_AT: &str = "@1792368000"; // 2026-10-19T00:00:00Z
+
+/// When the pre-v1 `/api/users` routes will be removed, as an RFC 8594 `Sunset` date.
+const USERS_SUNSET_AT: &str = "Mon, 19 Apr 2027 00:00:00 GMT";
+
+/// Migrations compiled into the binary; applied on startup and checked by `/readyz`.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Loads `.env`, connects to the database and applies pending migrations.
+pub async fn connect_db() -> MySqlPool {
     dotenv().ok();
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL not set");
     
@@ -16,40 +43,306 @@ pub async fn run_server() -> MySqlPool {
         .await
         .unwrap_or_else(|_| panic!("Failed to connect to database: {}", db_url));
 
-    let actix_pool = pool.clone();
+    // Bring the schema up to date before serving requests
+    MIGRATOR
+        .run(&pool)
+        .await
+        .expect("Failed to run database migrations");
+
+    pool
+}
+
+/// Starts the embedded Actix server; user changes made through it are published on `events`.
+pub async fn run_server(events: EventBus) -> MySqlPool {
+    let pool = connect_db().await;
+
+    // Permanently remove soft-deleted users once their retention period is over
+    spawn_purge_job(pool.clone());
+    spawn_webhook_jobs(pool.clone());
+
+    let server = build_server(pool.clone(), events).unwrap_or_else(|e| panic!("Failed to start server: {}", e));
 
     // Start Actix server in a separate task
     tokio::spawn(async move {
-        HttpServer::new(move || {
-            App::new()
-                .app_data(web::Data::new(actix_pool.clone()))
-                .wrap(Logger::default())
-                .service(
-                    web::scope("/auth")
-                        .route("/register", web::post().to(register))
-                        .route("/login", web::post().to(login)),
-                )
+        server.await.expect("Server run failed");
+    });
+
+    pool
+}
+
+/// Runs only the Actix server, without the Tauri window, until it shuts down.
+/// SIGTERM and SIGINT stop accepting connections and let in-flight requests finish.
+pub async fn serve() -> std::io::Result<()> {
+    let pool = connect_db().await;
+    spawn_purge_job(pool.clone());
+
+    let events = EventBus::new();
+    spawn_webhook_jobs(pool.clone());
+
+    let server = build_server(pool, events)?;
+    let handle = server.handle();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!("Shutting down gracefully");
+        handle.stop(true).await;
+    });
+
+    let result = server.await;
+    telemetry::shutdown_tracing().await;
+    result
+}
+
+/// Registers the probes and the `/auth` and `/api` routes. Each one is documented in `ApiDoc`.
+fn configure_routes(cfg: &mut web::ServiceConfig) {
+    // Unauthenticated probes for load balancers and monitoring
+    cfg.service(web::resource("/healthz").wrap(from_fn(rate_limit::limit)).route(web::get().to(healthz)))
+        .service(web::resource("/readyz").wrap(from_fn(rate_limit::limit)).route(web::get().to(readyz)))
+        .service(web::resource("/version").wrap(from_fn(rate_limit::limit)).route(web::get().to(version)))
+        .service(web::resource("/metrics").wrap(from_fn(rate_limit::limit)).route(web::get().to(metrics_endpoint)))
+        .service(
+            web::scope("/auth")
+                .route("/register", web::post().to(register))
+                .route("/login", web::post().to(login))
+                .route("/refresh", web::post().to(refresh))
+                .route("/logout", web::post().to(logout))
+                .route("/oidc/login", web::get().to(oidc_login))
+                .route("/oidc/callback", web::get().to(oidc_callback))
+                .route("/oidc/start", web::post().to(oidc_start))
+                .route("/oidc/exchange", web::post().to(oidc_exchange)),
+        )
+        // Everything under /api needs a token; nested scopes share the one middleware
+        .service(
+            web::scope("/api")
+                .wrap(from_fn(auth_middleware::check_auth_middleware))
+                .route("/protected", web::get().to(protected))
                 .service(
-                    web::scope("/api")
-                        .wrap(from_fn(auth_middleware::check_auth_middleware))
-                        .route("/protected", web::get().to(protected)),
+                    web::scope("/v1/users")
+                        .route("", web::get().to(fetch_all_users))
+                        .route("", web::post().to(create_user_v1))
+                        .route("/events", web::get().to(user_events))
+                        .route("/trash", web::get().to(fetch_deleted_users))
+                        .route("/{id}", web::get().to(get_user))
+                        .route("/{id}", web::put().to(replace_user_v1))
+                        .route("/{id}", web::patch().to(patch_user))
+                        .route("/{id}", web::delete().to(delete_user_v1))
+                        .route("/{id}/restore", web::post().to(restore_user)),
                 )
+                // Pre-v1 routes, kept as deprecated aliases until clients have moved over
                 .service(
-                    web::scope("/api/users")
-                        .wrap(from_fn(auth_middleware::check_auth_middleware))
+                    web::scope("/users")
+                        .wrap(
+                            DefaultHeaders::new()
+                                .add(("Deprecation", USERS_DEPRECATED_AT))
+                                .add(("Sunset", USERS_SUNSET_AT))
+                                .add((LINK, "</api/v1/users>; rel=\"successor-version\"")),
+                        )
                         .route("/create_user", web::post().to(create_user))
                         .route("/fetch_all_users", web::get().to(fetch_all_users))
                         .route("/update_user", web::put().to(update_user))
-                        .route("/delete_user/{id}", web::delete().to(delete_user)),
+                        .route("/delete_user/{id}", web::delete().to(delete_user))
+                        .route("/trash", web::get().to(fetch_deleted_users))
+                        .route("/events", web::get().to(user_events))
+                        .route("/{id}/restore", web::post().to(restore_user))
+                        .route("/{id}", web::get().to(get_user))
+                        .route("/{id}", web::patch().to(patch_user)),
                 )
-        })
-        .bind(("0.0.0.0", 8080))  // Bind to all IP addresses for wider accessibility
-        .expect("Failed to bind server")
-        .shutdown_timeout(Duration::from_secs(10))  // Graceful shutdown in 10 seconds
-        .run()
-        .await
-        .expect("Server run failed");
-    });
+                .service(
+                    web::scope("/webhooks")
+                        .route("", web::post().to(create_webhook))
+                        .route("", web::get().to(fetch_webhooks))
+                        .route("/{id}", web::delete().to(delete_webhook))
+                        .route("/{id}/deliveries", web::get().to(fetch_webhook_deliveries))
+                        .route("/deliveries/{id}/attempts", web::get().to(fetch_delivery_attempts))
+                        .route("/deliveries/{id}/retry", web::post().to(retry_webhook_delivery)),
+                )
+                .service(
+                    web::scope("/keys")
+                        .route("", web::post().to(create_api_key))
+                        .route("", web::get().to(fetch_api_keys))
+                        .route("/{id}", web::delete().to(revoke_api_key)),
+                ),
+        );
+}
 
-    pool
+/// Resolves on the first SIGINT (Ctrl+C) or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Binds the Actix server on top of the given pool, over HTTPS if `TLS_MODE` asks for it.
+/// The returned `Server` starts handling requests once it is awaited.
+pub fn build_server(pool: MySqlPool, events: EventBus) -> std::io::Result<Server> {
+    let openapi = ApiDoc::openapi();
+    let probe_limiter = web::Data::new(RateLimiter::probes_from_env());
+    let cors_config = CorsConfig::from_env();
+    let tls_config = TlsConfig::from_env();
+    let addr = server_addr();
+    let https_port = web::Data::new(HttpsPort::from_addr(&addr));
+    let service_roles = web::Data::new(
+        ServiceRoles::from_env().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+    );
+    // Single sign-on routes answer 404 unless a provider is configured
+    let oidc = OidcClient::from_env().map(web::Data::new);
+    let pending_logins = web::Data::new(PendingLogins::default());
+    // Password logins try the directory first when one is configured
+    let ldap = LdapConfig::from_env().map(web::Data::new);
+    let redirect = tls_config.as_ref().is_some_and(|tls| tls.redirect_addr.is_some());
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(events.clone()))
+            .app_data(probe_limiter.clone())
+            .app_data(https_port.clone())
+            .app_data(service_roles.clone())
+            .app_data(pending_logins.clone())
+            .configure(|cfg| {
+                if let Some(oidc) = &oidc {
+                    cfg.app_data(oidc.clone());
+                }
+                if let Some(ldap) = &ldap {
+                    cfg.app_data(ldap.clone());
+                }
+            })
+            .wrap(from_fn(metrics::track_requests))
+            // Answers preflights before they reach the auth check
+            .wrap(security::cors(&cors_config))
+            .wrap(from_fn(security::security_headers))
+            // Only the plain-HTTP redirect listener is affected; the TLS listener passes through
+            .wrap(Condition::new(redirect, from_fn(tls::redirect_to_https)))
+            // Outermost, so the request span and id cover everything else
+            .wrap(from_fn(telemetry::trace_requests))
+            // OpenAPI document at /openapi.json, interactive docs at /docs/
+            .service(SwaggerUi::new("/docs/{_:.*}").url("/openapi.json", openapi.clone()))
+            .configure(configure_routes)
+    })
+    // Client certificates are read once per connection and shared by its requests
+    .on_connect(mtls::capture_client_certificate);
+
+    let server = match &tls_config {
+        Some(tls_config) => {
+            let server = server.bind_rustls_0_23(&addr, tls::server_config(tls_config)?)?;
+            match &tls_config.redirect_addr {
+                Some(redirect_addr) => server.bind(redirect_addr)?,
+                None => server,
+            }
+        }
+        None => server.bind(&addr)?,
+    };
+
+    Ok(server
+        .shutdown_timeout(10)  // Graceful shutdown in 10 seconds
+        .disable_signals()  // Shutdown is driven by `serve` or by the host process
+        .run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::test;
+
+    use crate::utils::auth::create_jwt;
+    use crate::utils::models::User;
+
+    // Answered by the default service, i.e. by no registered route.
+    const UNROUTED: StatusCode = StatusCode::IM_A_TEAPOT;
+
+    fn admin_token() -> String {
+        std::env::set_var("JWT_SECRET", "route-test-secret");
+        let admin = User {
+            id: 1,
+            name: "Admin".to_string(),
+            email: "admin@example.com".to_string(),
+            role: "admin".to_string(),
+            password: String::new(),
+        };
+        create_jwt(&admin).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn every_documented_operation_is_routed() {
+        let token = admin_token();
+
+        // No database: routed requests fail in their handler, but never reach the default service
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(EventBus::new()))
+                .app_data(web::Data::new(PendingLogins::default()))
+                .configure(configure_routes)
+                .default_service(web::to(|| async { HttpResponse::new(UNROUTED) })),
+        )
+        .await;
+
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        for (path, item) in doc["paths"].as_object().unwrap() {
+            let uri: Vec<&str> = path
+                .split('/')
+                .map(|segment| if segment.starts_with('{') { "1" } else { segment })
+                .collect();
+            for method in item.as_object().unwrap().keys() {
+                let method = Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+                let req = test::TestRequest::default()
+                    .method(method.clone())
+                    .uri(&uri.join("/"))
+                    .insert_header((AUTHORIZATION, format!("Bearer {}", token)))
+                    .to_request();
+                let status = match test::try_call_service(&app, req).await {
+                    Ok(res) => res.status(),
+                    Err(err) => err.as_response_error().status_code(),
+                };
+                assert!(
+                    status != UNROUTED && status != StatusCode::METHOD_NOT_ALLOWED,
+                    "{} {} is documented but not routed",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+    #[actix_web::test]
+    async fn deprecated_routes_announce_their_sunset_and_successor() {
+        let token = admin_token();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(EventBus::new()))
+                .app_data(web::Data::new(PendingLogins::default()))
+                .configure(configure_routes),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/users/1")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get("Deprecation").unwrap(), "@1792368000");
+        assert_eq!(res.headers().get("Sunset").unwrap(), "Mon, 19 Apr 2027 00:00:00 GMT");
+        assert_eq!(res.headers().get(LINK).unwrap(), "</api/v1/users>; rel=\"successor-version\"");
+
+        let req = test::TestRequest::get()
+            .uri("/api/v1/users/1")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(res.headers().get("Deprecation").is_none());
+    }
 }
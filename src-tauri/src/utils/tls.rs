@@ -0,0 +1,329 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::LOCATION,
+    web, Error, HttpResponse,
+};
+use actix_web::middleware::Next;
+use rustls::crypto::{aws_lc_rs, CryptoProvider};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+
+use crate::utils::config::TlsConfig;
+use crate::utils::files::write_private;
+
+/// How often the certificate files are checked for changes.
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Builds the rustls config for the server. The certificate is reloaded whenever
+/// its files change, so renewals take effect without a restart.
+/// With a client CA, connections may present a client certificate signed by it.
+pub fn server_config(config: &TlsConfig) -> io::Result<ServerConfig> {
+    if config.self_signed {
+        ensure_self_signed(&config.cert_path, &config.key_path)?;
+    }
+
+    let provider = Arc::new(aws_lc_rs::default_provider());
+    let resolver = Arc::new(ReloadingResolver::new(
+        config.cert_path.clone(),
+        config.key_path.clone(),
+        provider.clone(),
+    )?);
+    spawn_reload_job(resolver.clone());
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let builder = match &config.client_ca_path {
+        Some(ca_path) => builder.with_client_cert_verifier(client_verifier(ca_path, provider)?),
+        None => builder.with_no_client_auth(),
+    };
+    Ok(builder.with_cert_resolver(resolver))
+}
+
+// Client certificates are optional, so users can still connect and send a JWT instead.
+// A certificate that is presented but does not chain to the CA fails the handshake.
+fn client_verifier(ca_path: &Path, provider: Arc<CryptoProvider>) -> io::Result<Arc<dyn ClientCertVerifier>> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut BufReader::new(File::open(ca_path)?)) {
+        roots.add(cert?).map_err(|e| invalid(format!("bad CA certificate in {}: {}", ca_path.display(), e)))?;
+    }
+    if roots.is_empty() {
+        return Err(invalid(format!("no CA certificate in {}", ca_path.display())));
+    }
+
+    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+        .allow_unauthenticated()
+        .build()
+        .map_err(|e| invalid(e.to_string()))
+}
+
+/// Serves the most recently loaded certificate to every handshake.
+#[derive(Debug)]
+struct ReloadingResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadingResolver {
+    fn new(cert_path: PathBuf, key_path: PathBuf, provider: Arc<CryptoProvider>) -> io::Result<Self> {
+        let current = load_certified_key(&cert_path, &key_path, &provider)?;
+        Ok(ReloadingResolver {
+            cert_path,
+            key_path,
+            provider,
+            current: RwLock::new(Arc::new(current)),
+        })
+    }
+
+    // Newest modification time of the two files; a renewal may replace them one at a time.
+    fn modified(&self) -> Option<SystemTime> {
+        let cert = fs::metadata(&self.cert_path).and_then(|m| m.modified()).ok()?;
+        let key = fs::metadata(&self.key_path).and_then(|m| m.modified()).ok()?;
+        Some(cert.max(key))
+    }
+
+    fn reload(&self) -> io::Result<()> {
+        let key = load_certified_key(&self.cert_path, &self.key_path, &self.provider)?;
+        *self.current.write().unwrap() = Arc::new(key);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+// A failed reload, e.g. while the files are half written, keeps the previous certificate.
+fn spawn_reload_job(resolver: Arc<ReloadingResolver>) {
+    tokio::spawn(async move {
+        let mut loaded = resolver.modified();
+        let mut interval = tokio::time::interval(RELOAD_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let modified = resolver.modified();
+            if modified.is_none() || modified == loaded {
+                continue;
+            }
+            match resolver.reload() {
+                Ok(()) => {
+                    loaded = modified;
+                    tracing::info!(path = %resolver.cert_path.display(), "Reloaded TLS certificate");
+                }
+                Err(e) => tracing::warn!(error = %e, "Failed to reload TLS certificate; keeping the current one"),
+            }
+        }
+    });
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path, provider: &CryptoProvider) -> io::Result<CertifiedKey> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?)).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(invalid(format!("no certificate in {}", cert_path.display())));
+    }
+
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?
+        .ok_or_else(|| invalid(format!("no private key in {}", key_path.display())))?;
+    let key = provider
+        .key_provider
+        .load_private_key(key)
+        .map_err(|e| invalid(format!("unusable private key in {}: {}", key_path.display(), e)))?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// Writes a self-signed certificate for `localhost` and `127.0.0.1` unless both files exist.
+/// Browsers and clients will not trust it; it is meant for local development only.
+fn ensure_self_signed(cert_path: &Path, key_path: &Path) -> io::Result<()> {
+    if cert_path.exists() && key_path.exists() {
+        return Ok(());
+    }
+
+    let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string(), "127.0.0.1".to_string()])
+        .map_err(io::Error::other)?;
+
+    for path in [cert_path, key_path] {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+    }
+    fs::write(cert_path, generated.cert.pem())?;
+    write_private(key_path, generated.key_pair.serialize_pem().as_bytes())?;
+
+    tracing::warn!(path = %cert_path.display(), "Generated a self-signed TLS certificate for development");
+    Ok(())
+}
+
+/// Port the HTTPS listener is reachable on, for redirects from plain HTTP.
+pub struct HttpsPort(pub u16);
+
+impl HttpsPort {
+    /// Takes the port from a bind address such as `0.0.0.0:8443`; 443 if it has none.
+    pub fn from_addr(addr: &str) -> Self {
+        HttpsPort(addr.rsplit_once(':').and_then(|(_, port)| port.parse().ok()).unwrap_or(443))
+    }
+}
+
+/// Middleware that answers requests arriving over plain HTTP with a permanent redirect
+/// to the same path over HTTPS. Requests on the TLS listener pass through.
+pub async fn redirect_to_https(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if req.app_config().secure() {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    let port = req.app_data::<web::Data<HttpsPort>>().map_or(443, |p| p.0);
+    let host = without_port(req.connection_info().host()).to_string();
+    let path = req.uri().path_and_query().map_or("/", |pq| pq.as_str()).to_string();
+    let location = if port == 443 {
+        format!("https://{}{}", host, path)
+    } else {
+        format!("https://{}:{}{}", host, port, path)
+    };
+
+    // 308 rather than 301, so clients repeat a POST as a POST
+    let res = HttpResponse::PermanentRedirect().insert_header((LOCATION, location)).finish();
+    Ok(req.into_response(res).map_into_right_body())
+}
+
+// Strips the port from a Host value, leaving bracketed IPv6 addresses intact.
+fn without_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port))
+            if port.bytes().all(|b| b.is_ascii_digit()) && (!name.contains(':') || name.ends_with(']')) =>
+        {
+            name
+        }
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::auth::random_token;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(random_token(8));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn resolver(dir: &Path) -> io::Result<ReloadingResolver> {
+        ReloadingResolver::new(dir.join("cert.pem"), dir.join("key.pem"), Arc::new(aws_lc_rs::default_provider()))
+    }
+
+    fn current_cert(resolver: &ReloadingResolver) -> Vec<u8> {
+        resolver.current.read().unwrap().cert[0].to_vec()
+    }
+
+    #[test]
+    fn without_port_strips_the_port() {
+        assert_eq!(without_port("example.com:8080"), "example.com");
+        assert_eq!(without_port("127.0.0.1:80"), "127.0.0.1");
+        assert_eq!(without_port("[::1]:443"), "[::1]");
+    }
+
+    #[test]
+    fn without_port_keeps_hosts_without_one() {
+        assert_eq!(without_port("example.com"), "example.com");
+        assert_eq!(without_port("[::1]"), "[::1]");
+        assert_eq!(without_port("::1"), "::1");
+        assert_eq!(without_port("example.com:http"), "example.com:http");
+    }
+
+    #[test]
+    fn https_port_from_addr() {
+        assert_eq!(HttpsPort::from_addr("0.0.0.0:8443").0, 8443);
+        assert_eq!(HttpsPort::from_addr("[::1]:443").0, 443);
+        assert_eq!(HttpsPort::from_addr("[::]:9443").0, 9443);
+    }
+
+    #[test]
+    fn https_port_defaults_to_443() {
+        assert_eq!(HttpsPort::from_addr("localhost").0, 443);
+        assert_eq!(HttpsPort::from_addr("[::1]").0, 443);
+        assert_eq!(HttpsPort::from_addr("localhost:https").0, 443);
+    }
+
+    #[test]
+    fn self_signed_is_only_generated_once() {
+        let dir = temp_dir();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+
+        ensure_self_signed(&cert, &key).unwrap();
+        let first = fs::read(&cert).unwrap();
+        ensure_self_signed(&cert, &key).unwrap();
+        assert_eq!(fs::read(&cert).unwrap(), first);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resolver_picks_up_a_renewed_certificate() {
+        let dir = temp_dir();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        ensure_self_signed(&cert, &key).unwrap();
+        let resolver = resolver(&dir).unwrap();
+        let first = current_cert(&resolver);
+        assert!(resolver.modified().is_some());
+
+        fs::remove_file(&cert).unwrap();
+        assert_eq!(resolver.modified(), None);
+        fs::remove_file(&key).unwrap();
+        ensure_self_signed(&cert, &key).unwrap();
+
+        resolver.reload().unwrap();
+        let renewed = current_cert(&resolver);
+        assert_ne!(renewed, first);
+        let on_disk = rustls_pemfile::certs(&mut BufReader::new(File::open(&cert).unwrap())).next().unwrap().unwrap();
+        assert_eq!(renewed, on_disk.to_vec());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_reload_keeps_the_current_certificate() {
+        let dir = temp_dir();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        ensure_self_signed(&cert, &key).unwrap();
+        let resolver = resolver(&dir).unwrap();
+        let loaded = current_cert(&resolver);
+
+        fs::write(&cert, "half written").unwrap();
+        assert!(resolver.reload().is_err());
+        assert_eq!(current_cert(&resolver), loaded);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resolver_requires_a_certificate_and_key() {
+        let dir = temp_dir();
+        assert!(resolver(&dir).is_err());
+
+        fs::write(dir.join("cert.pem"), "").unwrap();
+        fs::write(dir.join("key.pem"), "").unwrap();
+        assert_eq!(resolver(&dir).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
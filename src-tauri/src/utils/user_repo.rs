@@ -0,0 +1,513 @@
+use sqlx::mysql::MySqlExecutor;
+use sqlx::{MySql, MySqlPool, Transaction};
+
+use crate::utils::app_error::AppError;
+use crate::utils::events::UserEvent;
+use crate::utils::refresh_tokens;
+use crate::utils::models::{DeletedUser, NewUser, PatchUser, PublicUser, UpdateUser, User};
+use crate::utils::webhooks;
+
+/// Result of a version-checked update.
+pub enum UpdateOutcome {
+    Updated(PublicUser),
+    NotFound,
+    /// The row exists but its version differs from the expected one.
+    Stale(PublicUser),
+}
+
+/// An account at an external identity source that a local user can be linked to.
+#[derive(Debug, Clone, Copy)]
+pub enum ExternalId<'a> {
+    /// The `sub` claim of an OpenID Connect provider.
+    OidcSubject(&'a str),
+    /// The DN of a directory entry.
+    LdapDn(&'a str),
+}
+
+/// A write that could take away a user's admin rights.
+enum AdminChange<'a> {
+    Delete,
+    SetRole(&'a str),
+}
+
+/// Fetches a single active user by id, without the password hash.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn find_public_user<'e>(
+    db: impl MySqlExecutor<'e>,
+    id: i32,
+) -> Result<Option<PublicUser>, sqlx::Error> {
+    sqlx::query_as!(
+        PublicUser,
+        "SELECT id, name, email, role, version FROM users WHERE id = ? AND deleted_at IS NULL",
+        id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Fetches an active user by email, including the password hash.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn find_user_by_email(db: &MySqlPool, email: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as!(
+        User,
+        "SELECT id, name, email, role, password FROM users WHERE email = ? AND deleted_at IS NULL",
+        email
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Fetches the active user linked to an external account.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn find_user_by_external_id(db: &MySqlPool, external: ExternalId<'_>) -> Result<Option<User>, sqlx::Error> {
+    match external {
+        ExternalId::OidcSubject(subject) => sqlx::query_as!(
+            User,
+            "SELECT id, name, email, role, password FROM users WHERE oidc_subject = ? AND deleted_at IS NULL",
+            subject
+        )
+        .fetch_optional(db)
+        .await,
+        ExternalId::LdapDn(dn) => sqlx::query_as!(
+            User,
+            "SELECT id, name, email, role, password FROM users WHERE ldap_dn = ? AND deleted_at IS NULL",
+            dn
+        )
+        .fetch_optional(db)
+        .await,
+    }
+}
+
+/// Links a user to an external account. A user linked to a directory entry can only log in through the directory.
+/// Returns `false` if the user is already linked to another account of that kind.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn link_external_id<'e>(
+    db: impl MySqlExecutor<'e>,
+    id: i32,
+    external: ExternalId<'_>,
+) -> Result<bool, sqlx::Error> {
+    let result = match external {
+        ExternalId::OidcSubject(subject) => sqlx::query!(
+            "UPDATE users SET oidc_subject = ? WHERE id = ? AND oidc_subject IS NULL",
+            subject,
+            id
+        )
+        .execute(db)
+        .await?,
+        ExternalId::LdapDn(dn) => sqlx::query!(
+            "UPDATE users SET ldap_dn = ? WHERE id = ? AND ldap_dn IS NULL",
+            dn,
+            id
+        )
+        .execute(db)
+        .await?,
+    };
+    Ok(result.rows_affected() > 0)
+}
+
+/// Fetches the active user with the given email, unless it logs in through the directory.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn find_local_user_by_email(db: &MySqlPool, email: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as!(
+        User,
+        "SELECT id, name, email, role, password FROM users WHERE email = ? AND ldap_dn IS NULL AND deleted_at IS NULL",
+        email
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Renames a user, e.g. to follow a change in the directory.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn set_name(db: &MySqlPool, id: i32, name: &str) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
+    sqlx::query!(
+        "UPDATE users SET name = ?, version = version + 1 WHERE id = ?",
+        name,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if let Some(user) = find_public_user(&mut *tx, id).await? {
+        webhooks::enqueue(&mut *tx, &UserEvent::Updated(user)).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Lists all active users.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn list_users(db: &MySqlPool) -> Result<Vec<PublicUser>, sqlx::Error> {
+    sqlx::query_as!(
+        PublicUser,
+        "SELECT id, name, email, role, version FROM users WHERE deleted_at IS NULL"
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Inserts a new user with an already hashed password.
+/// Returns the new user's id; a taken email is a conflict, also while its user is in the trash.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn insert_user(db: &MySqlPool, user: &NewUser, password_hash: &str) -> Result<u64, AppError> {
+    let mut tx = db.begin().await?;
+    let id = insert_user_in(&mut tx, user, password_hash).await?;
+    tx.commit().await?;
+    Ok(id)
+}
+
+/// Like `insert_user`, but inside the caller's transaction.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn insert_user_in(
+    tx: &mut Transaction<'_, MySql>,
+    user: &NewUser,
+    password_hash: &str,
+) -> Result<u64, AppError> {
+    let inserted = sqlx::query!(
+        "INSERT INTO users (name, email, password, role) VALUES (?, ?, ?, ?)",
+        user.name,
+        user.email,
+        password_hash,
+        user.role
+    )
+    .execute(&mut **tx)
+    .await;
+
+    let result = match inserted {
+        Ok(result) => result,
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => return Err(email_conflict(tx, &user.email).await),
+        Err(e) => return Err(e.into()),
+    };
+
+    let id = result.last_insert_id();
+    if let Some(created) = find_public_user(&mut **tx, id as i32).await? {
+        webhooks::enqueue(&mut **tx, &UserEvent::Created(created)).await?;
+    }
+    Ok(id)
+}
+
+// The error for a write that hit the unique index on `email`.
+// Soft-deleted users keep their email until they are purged.
+async fn email_conflict(tx: &mut Transaction<'_, MySql>, email: &str) -> AppError {
+    let deleted = sqlx::query_scalar!(
+        "SELECT deleted_at IS NOT NULL AS `deleted: bool` FROM users WHERE email = ?",
+        email
+    )
+    .fetch_optional(&mut **tx)
+    .await;
+
+    match deleted {
+        Ok(Some(true)) => AppError::email_deleted(),
+        Ok(_) => AppError::email_taken(),
+        Err(e) => e.into(),
+    }
+}
+
+/// Replaces an active user's password hash and revokes their refresh tokens.
+/// Returns `false` if no active user has the given email.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn set_password(db: &MySqlPool, email: &str, password_hash: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "UPDATE users SET password = ?, version = version + 1 WHERE email = ? AND deleted_at IS NULL",
+        password_hash,
+        email
+    )
+    .execute(db)
+    .await?;
+
+    // Remembered sessions must not outlive the old password
+    refresh_tokens::revoke_all_for_email(db, email).await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Locks the target row and rejects changes that would leave no administrator
+/// or lock the acting admin out of their own account.
+/// `actor_id` is the acting user's id, `None` for local CLI commands, API keys and services.
+/// Returns `false` if no active user has the given id.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+async fn enforce_admin_guardrails(
+    tx: &mut Transaction<'_, MySql>,
+    id: i32,
+    actor_id: Option<i32>,
+    change: AdminChange<'_>,
+) -> Result<bool, AppError> {
+    let role = sqlx::query_scalar!("SELECT role FROM users WHERE id = ? AND deleted_at IS NULL FOR UPDATE", id)
+        .fetch_optional(&mut **tx)
+        .await?;
+
+    let role = match role {
+        Some(r) => r,
+        None => return Ok(false),
+    };
+
+    // Ids, not emails: the login typed or the email in an older token may not match the row
+    if !check_admin_change(&role, actor_id == Some(id), &change)? {
+        return Ok(true);
+    }
+
+    // Lock every admin row so concurrent demotions are serialized
+    let admins = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM users WHERE role = 'admin' AND deleted_at IS NULL FOR UPDATE"
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    ensure_admin_remains(admins)?;
+    Ok(true)
+}
+
+/// Rejects deleting or demoting yourself. Returns whether the change takes admin rights
+/// away from a user whose role is `target_role`, which needs another admin to remain.
+fn check_admin_change(target_role: &str, is_self: bool, change: &AdminChange<'_>) -> Result<bool, AppError> {
+    let removes_admin = target_role == "admin"
+        && match change {
+            AdminChange::Delete => true,
+            AdminChange::SetRole(role) => *role != "admin",
+        };
+
+    if is_self && matches!(change, AdminChange::Delete) {
+        return Err(AppError::self_delete());
+    }
+    if removes_admin && is_self {
+        return Err(AppError::self_demote());
+    }
+    Ok(removes_admin)
+}
+
+/// Rejects taking admin rights away when `admins`, counting the target, is the last one.
+fn ensure_admin_remains(admins: i64) -> Result<(), AppError> {
+    if admins <= 1 {
+        return Err(AppError::last_admin());
+    }
+    Ok(())
+}
+
+/// Updates a user's name, email and role if the row is still at `expected_version`.
+/// Every successful update bumps the version by one; an email another user has is a conflict.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn update_user(
+    db: &MySqlPool,
+    user: &UpdateUser,
+    expected_version: i32,
+    actor_id: Option<i32>,
+) -> Result<UpdateOutcome, AppError> {
+    let mut tx = db.begin().await?;
+
+    // The version is checked first, so a stale edit is a 412 even if it would also trip a guardrail
+    let version = sqlx::query_scalar!(
+        "SELECT version FROM users WHERE id = ? AND deleted_at IS NULL FOR UPDATE",
+        user.id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    match version {
+        None => return Ok(UpdateOutcome::NotFound),
+        Some(v) if v != expected_version => {
+            return Ok(match find_public_user(&mut *tx, user.id).await? {
+                Some(current) => UpdateOutcome::Stale(current),
+                None => UpdateOutcome::NotFound,
+            });
+        }
+        Some(_) => {}
+    }
+
+    if !enforce_admin_guardrails(&mut tx, user.id, actor_id, AdminChange::SetRole(&user.role)).await? {
+        return Ok(UpdateOutcome::NotFound);
+    }
+
+    let updated = sqlx::query!(
+        "UPDATE users SET name = ?, email = ?, role = ?, version = version + 1 WHERE id = ? AND deleted_at IS NULL",
+        user.name,
+        user.email,
+        user.role,
+        user.id
+    )
+    .execute(&mut *tx)
+    .await;
+    match updated {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => return Err(email_conflict(&mut tx, &user.email).await),
+        Err(e) => return Err(e.into()),
+    }
+
+    let Some(current) = find_public_user(&mut *tx, user.id).await? else {
+        return Ok(UpdateOutcome::NotFound);
+    };
+    webhooks::enqueue(&mut *tx, &UserEvent::Updated(current.clone())).await?;
+    tx.commit().await?;
+
+    Ok(UpdateOutcome::Updated(current))
+}
+
+/// Applies a validated merge patch on top of the stored user.
+/// Absent fields keep their current value; the write is checked like `update_user`.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn patch_user(
+    db: &MySqlPool,
+    id: i32,
+    patch: &PatchUser,
+    expected_version: i32,
+    actor_id: Option<i32>,
+) -> Result<UpdateOutcome, AppError> {
+    let current = match find_public_user(db, id).await? {
+        Some(u) => u,
+        None => return Ok(UpdateOutcome::NotFound),
+    };
+
+    let merged = UpdateUser {
+        id,
+        name: patch.name.clone().flatten().unwrap_or(current.name),
+        email: patch.email.clone().flatten().unwrap_or(current.email),
+        role: patch.role.clone().flatten().unwrap_or(current.role),
+        version: None,
+    };
+
+    update_user(db, &merged, expected_version, actor_id).await
+}
+
+/// Soft-deletes a user by setting `deleted_at`.
+/// Returns the user as it was before deletion, or `None` if no active user has the given id.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn delete_user(db: &MySqlPool, id: i32, actor_id: Option<i32>) -> Result<Option<PublicUser>, AppError> {
+    let mut tx = db.begin().await?;
+
+    if !enforce_admin_guardrails(&mut tx, id, actor_id, AdminChange::Delete).await? {
+        return Ok(None);
+    }
+
+    let user = find_public_user(&mut *tx, id).await?;
+
+    sqlx::query!(
+        "UPDATE users SET deleted_at = UTC_TIMESTAMP(), version = version + 1 WHERE id = ? AND deleted_at IS NULL",
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if let Some(deleted) = &user {
+        webhooks::enqueue(&mut *tx, &UserEvent::Deleted(deleted.clone())).await?;
+    }
+    tx.commit().await?;
+    Ok(user)
+}
+
+/// Sets the role of the active user with the given email, subject to the admin guardrails.
+/// Used by local CLI commands; returns `None` if no active user has that email.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn set_role(db: &MySqlPool, email: &str, role: &str) -> Result<Option<PublicUser>, AppError> {
+    let id = match find_user_by_email(db, email).await? {
+        Some(u) => u.id,
+        None => return Ok(None),
+    };
+
+    let mut tx = db.begin().await?;
+    if !enforce_admin_guardrails(&mut tx, id, None, AdminChange::SetRole(role)).await? {
+        return Ok(None);
+    }
+
+    sqlx::query!(
+        "UPDATE users SET role = ?, version = version + 1 WHERE id = ?",
+        role,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let user = find_public_user(&mut *tx, id).await?;
+    if let Some(updated) = &user {
+        webhooks::enqueue(&mut *tx, &UserEvent::Updated(updated.clone())).await?;
+    }
+    tx.commit().await?;
+    Ok(user)
+}
+
+/// Lists soft-deleted users, most recently deleted first.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn list_deleted_users(db: &MySqlPool) -> Result<Vec<DeletedUser>, sqlx::Error> {
+    sqlx::query_as!(
+        DeletedUser,
+        "SELECT id, name, email, role, deleted_at AS `deleted_at!` FROM users WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC"
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Restores a soft-deleted user.
+/// Returns the restored user, or `None` if no deleted user has the given id.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn restore_user(db: &MySqlPool, id: i32) -> Result<Option<PublicUser>, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let result = sqlx::query!(
+        "UPDATE users SET deleted_at = NULL, version = version + 1 WHERE id = ? AND deleted_at IS NOT NULL",
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+    let user = find_public_user(&mut *tx, id).await?;
+    if let Some(restored) = &user {
+        webhooks::enqueue(&mut *tx, &UserEvent::Created(restored.clone())).await?;
+    }
+    tx.commit().await?;
+    Ok(user)
+}
+
+/// Permanently removes users that were soft-deleted more than `retention_days` ago.
+/// Returns the number of purged rows.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn purge_deleted_users(db: &MySqlPool, retention_days: u32) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM users WHERE deleted_at IS NOT NULL AND deleted_at < UTC_TIMESTAMP() - INTERVAL ? DAY",
+        retention_days
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_code(result: Result<bool, AppError>) -> String {
+        result.unwrap_err().code.into_owned()
+    }
+
+    #[test]
+    fn admins_cannot_delete_or_demote_themselves() {
+        assert_eq!(error_code(check_admin_change("admin", true, &AdminChange::Delete)), "SELF_DELETE");
+        assert_eq!(error_code(check_admin_change("admin", true, &AdminChange::SetRole("user"))), "SELF_DEMOTE");
+        assert_eq!(error_code(check_admin_change("admin", true, &AdminChange::SetRole("manager"))), "SELF_DEMOTE");
+    }
+
+    #[test]
+    fn self_delete_is_refused_for_any_role() {
+        assert_eq!(error_code(check_admin_change("manager", true, &AdminChange::Delete)), "SELF_DELETE");
+    }
+
+    #[test]
+    fn removing_another_admin_needs_the_admin_count() {
+        assert!(check_admin_change("admin", false, &AdminChange::Delete).unwrap());
+        assert!(check_admin_change("admin", false, &AdminChange::SetRole("user")).unwrap());
+    }
+
+    #[test]
+    fn the_last_admin_cannot_be_removed() {
+        assert_eq!(ensure_admin_remains(1).unwrap_err().code, "LAST_ADMIN");
+        assert!(ensure_admin_remains(2).is_ok());
+    }
+
+    #[test]
+    fn changes_that_keep_admins_are_allowed() {
+        assert!(!check_admin_change("admin", true, &AdminChange::SetRole("admin")).unwrap());
+        assert!(!check_admin_change("user", false, &AdminChange::Delete).unwrap());
+        assert!(!check_admin_change("user", false, &AdminChange::SetRole("admin")).unwrap());
+        assert!(!check_admin_change("manager", true, &AdminChange::SetRole("user")).unwrap());
+    }
+}
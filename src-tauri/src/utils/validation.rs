@@ -0,0 +1,74 @@
+use crate::utils::app_error::AppError;
+use crate::utils::models::PatchUser;
+use crate::utils::permissions::ROLES;
+
+/// Validates each field present in the patch on its own.
+pub fn validate_patch(patch: &PatchUser) -> Result<(), AppError> {
+    if let Some(name) = &patch.name {
+        match name {
+            Some(n) if !n.trim().is_empty() => {}
+            _ => return Err(AppError::bad_request("name cannot be blank")),
+        }
+    }
+
+    if let Some(email) = &patch.email {
+        match email {
+            Some(e) if e.contains('@') && !e.trim().is_empty() => {}
+            _ => return Err(AppError::bad_request("email must be a valid address")),
+        }
+    }
+
+    if let Some(role) = &patch.role {
+        validate_role(role.as_deref().unwrap_or_default())?;
+    }
+
+    Ok(())
+}
+
+/// Checks that the role is one of `ROLES`.
+pub fn validate_role(role: &str) -> Result<(), AppError> {
+    if !ROLES.contains(&role) {
+        return Err(AppError::bad_request("role must be one of 'admin', 'manager' or 'user'"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(name: Option<Option<&str>>, email: Option<Option<&str>>, role: Option<Option<&str>>) -> PatchUser {
+        let own = |field: Option<Option<&str>>| field.map(|v| v.map(str::to_string));
+        PatchUser { name: own(name), email: own(email), role: own(role), version: None }
+    }
+
+    #[test]
+    fn empty_patch_is_valid() {
+        assert!(validate_patch(&PatchUser::default()).is_ok());
+    }
+
+    #[test]
+    fn present_fields_are_validated() {
+        assert!(validate_patch(&patch(Some(Some("Ada")), Some(Some("ada@example.org")), Some(Some("manager")))).is_ok());
+
+        assert!(validate_patch(&patch(Some(Some("  ")), None, None)).is_err());
+        assert!(validate_patch(&patch(None, Some(Some("not-an-email")), None)).is_err());
+        assert!(validate_patch(&patch(None, None, Some(Some("root")))).is_err());
+    }
+
+    #[test]
+    fn nulls_are_rejected() {
+        assert!(validate_patch(&patch(Some(None), None, None)).is_err());
+        assert!(validate_patch(&patch(None, Some(None), None)).is_err());
+        assert!(validate_patch(&patch(None, None, Some(None))).is_err());
+    }
+
+    #[test]
+    fn only_known_roles_are_valid() {
+        for role in ROLES {
+            assert!(validate_role(role).is_ok());
+        }
+        assert!(validate_role("Admin").is_err());
+        assert!(validate_role("").is_err());
+    }
+}
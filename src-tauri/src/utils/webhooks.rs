@@ -0,0 +1,395 @@
+use std::time::{Duration, Instant};
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use chrono::{NaiveDateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use reqwest::Client;
+use sha2::Sha256;
+use sqlx::mysql::MySqlExecutor;
+use sqlx::MySqlPool;
+
+use crate::utils::config::interval_from_env;
+use crate::utils::events::UserEvent;
+use crate::utils::models::{DeliveryAttempt, Webhook, WebhookDelivery};
+
+/// Events a webhook can subscribe to.
+pub const WEBHOOK_EVENTS: [&str; 3] = ["user-created", "user-updated", "user-deleted"];
+
+/// Attempts after which a delivery is moved to the dead-letter state.
+const MAX_ATTEMPTS: i32 = 8;
+
+/// Delay before the first retry; doubled after every further failure.
+const BASE_BACKOFF_SECS: i64 = 30;
+
+/// Upper bound for the retry delay.
+const MAX_BACKOFF_SECS: i64 = 6 * 60 * 60;
+
+/// Seconds between outbox polls, unless `WEBHOOK_POLL_SECONDS` is set.
+const DEFAULT_POLL_SECONDS: u64 = 5;
+
+/// Deliveries sent per poll.
+const BATCH_SIZE: i64 = 50;
+
+/// How long a claimed delivery is reserved for the worker that claimed it.
+/// Longer than a full batch at the HTTP timeout, so it is only sent again if that worker died.
+const CLAIM_SECS: i64 = 15 * 60;
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+struct WebhookRow {
+    id: i32,
+    url: String,
+    events: String,
+    active: bool,
+    created_at: NaiveDateTime,
+}
+
+impl From<WebhookRow> for Webhook {
+    fn from(row: WebhookRow) -> Self {
+        Webhook {
+            id: row.id,
+            url: row.url,
+            events: row.events.split(',').map(str::to_string).collect(),
+            active: row.active,
+            created_at: row.created_at,
+        }
+    }
+}
+
+struct DueDelivery {
+    id: i64,
+    event: String,
+    payload: String,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+/// Signs `"{timestamp}.{body}"` with HMAC-SHA256 and returns the header value (`sha256=<hex>`).
+/// Receivers should recompute it and reject old timestamps to prevent replays.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Creates a subscription and returns it with its newly generated signing secret.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn create_webhook(db: &MySqlPool, url: &str, events: &[String]) -> Result<(Webhook, String), sqlx::Error> {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let secret = hex::encode(bytes);
+
+    let id = sqlx::query!(
+        "INSERT INTO webhooks (url, secret, events) VALUES (?, ?, ?)",
+        url,
+        secret,
+        events.join(",")
+    )
+    .execute(db)
+    .await?
+    .last_insert_id();
+
+    let row = sqlx::query_as!(
+        WebhookRow,
+        "SELECT id, url, events, active AS `active: bool`, created_at FROM webhooks WHERE id = ?",
+        id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok((row.into(), secret))
+}
+
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn list_webhooks(db: &MySqlPool) -> Result<Vec<Webhook>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        WebhookRow,
+        "SELECT id, url, events, active AS `active: bool`, created_at FROM webhooks ORDER BY id"
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows.into_iter().map(Webhook::from).collect())
+}
+
+/// Deletes a subscription along with its outbox and delivery log.
+/// Returns `false` if no webhook has the given id.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn delete_webhook(db: &MySqlPool, id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM webhooks WHERE id = ?", id).execute(db).await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Lists the most recent deliveries of a webhook, newest first.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn list_deliveries(db: &MySqlPool, webhook_id: i32) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        WebhookDelivery,
+        "SELECT id, webhook_id, event, status, attempts, next_attempt_at, created_at, delivered_at FROM webhook_deliveries WHERE webhook_id = ? ORDER BY id DESC LIMIT 100",
+        webhook_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Lists every HTTP attempt of a delivery, oldest first.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn list_attempts(db: &MySqlPool, delivery_id: i64) -> Result<Vec<DeliveryAttempt>, sqlx::Error> {
+    sqlx::query_as!(
+        DeliveryAttempt,
+        "SELECT id, delivery_id, attempted_at, status_code, error, duration_ms FROM webhook_delivery_attempts WHERE delivery_id = ? ORDER BY id",
+        delivery_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Moves a dead delivery back into the outbox for immediate delivery.
+/// Returns `false` if no dead delivery has the given id.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn retry_delivery(db: &MySqlPool, id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "UPDATE webhook_deliveries SET status = 'pending', attempts = 0, next_attempt_at = UTC_TIMESTAMP() WHERE id = ? AND status = 'dead'",
+        id
+    )
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Queues the event for every active webhook subscribed to it.
+/// Called inside the transaction that changes the user, so the change and its deliveries commit together.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+pub async fn enqueue<'e>(db: impl MySqlExecutor<'e>, event: &UserEvent) -> Result<u64, sqlx::Error> {
+    let name = event.name();
+    let payload = serde_json::json!({
+        "event": name,
+        "occurred_at": Utc::now(),
+        "user": event.user(),
+    })
+    .to_string();
+
+    let result = sqlx::query!(
+        "INSERT INTO webhook_deliveries (webhook_id, event, payload, next_attempt_at) SELECT id, ?, ?, UTC_TIMESTAMP() FROM webhooks WHERE active AND FIND_IN_SET(?, events)",
+        name,
+        payload,
+        name
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Reserves a batch of due deliveries for this worker by pushing their next attempt past the claim period.
+/// Rows locked by another worker are skipped, so no delivery is handed to two workers at once.
+#[tracing::instrument(skip_all, fields(db.system = "mysql", otel.kind = "client"))]
+async fn claim_due(db: &MySqlPool) -> Result<Vec<DueDelivery>, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let due = sqlx::query_as!(
+        DueDelivery,
+        "SELECT d.id, d.event, d.payload, d.attempts, w.url, w.secret FROM webhook_deliveries d JOIN webhooks w ON w.id = d.webhook_id WHERE d.status = 'pending' AND d.next_attempt_at <= UTC_TIMESTAMP() AND w.active ORDER BY d.id LIMIT ? FOR UPDATE OF d SKIP LOCKED",
+        BATCH_SIZE
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for delivery in &due {
+        sqlx::query!(
+            "UPDATE webhook_deliveries SET next_attempt_at = UTC_TIMESTAMP() + INTERVAL ? SECOND WHERE id = ?",
+            CLAIM_SECS,
+            delivery.id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(due)
+}
+
+/// Sends every due delivery once and records the outcome.
+async fn deliver_due(db: &MySqlPool, http: &Client) -> Result<(), sqlx::Error> {
+    let due = claim_due(db).await?;
+
+    for delivery in due {
+        let timestamp = Utc::now().timestamp();
+        let started = Instant::now();
+        let result = http
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, &delivery.event)
+            .header(DELIVERY_HEADER, delivery.id.to_string())
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, sign(&delivery.secret, timestamp, &delivery.payload))
+            .body(delivery.payload.clone())
+            .send()
+            .await;
+        let duration_ms = started.elapsed().as_millis() as i32;
+
+        let (status_code, error) = match result {
+            Ok(resp) if resp.status().is_success() => (Some(resp.status().as_u16() as i32), None),
+            Ok(resp) => (Some(resp.status().as_u16() as i32), Some(format!("Receiver answered {}", resp.status()))),
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+        sqlx::query!(
+            "INSERT INTO webhook_delivery_attempts (delivery_id, status_code, error, duration_ms, attempted_at) VALUES (?, ?, ?, ?, UTC_TIMESTAMP())",
+            delivery.id,
+            status_code,
+            error,
+            duration_ms
+        )
+        .execute(db)
+        .await?;
+
+        let attempts = delivery.attempts + 1;
+        if error.is_none() {
+            sqlx::query!(
+                "UPDATE webhook_deliveries SET status = 'delivered', attempts = ?, delivered_at = UTC_TIMESTAMP() WHERE id = ?",
+                attempts,
+                delivery.id
+            )
+            .execute(db)
+            .await?;
+        } else if attempts >= MAX_ATTEMPTS {
+            tracing::warn!(delivery_id = delivery.id, attempts, "Webhook delivery is dead");
+            sqlx::query!(
+                "UPDATE webhook_deliveries SET status = 'dead', attempts = ? WHERE id = ?",
+                attempts,
+                delivery.id
+            )
+            .execute(db)
+            .await?;
+        } else {
+            sqlx::query!(
+                "UPDATE webhook_deliveries SET attempts = ?, next_attempt_at = UTC_TIMESTAMP() + INTERVAL ? SECOND WHERE id = ?",
+                attempts,
+                backoff_secs(attempts),
+                delivery.id
+            )
+            .execute(db)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+// 30s, 1m, 2m, 4m, ... capped at 6h.
+fn backoff_secs(attempts: i32) -> i64 {
+    let exponent = (attempts - 1).clamp(0, 20) as u32;
+    (BASE_BACKOFF_SECS << exponent).min(MAX_BACKOFF_SECS)
+}
+
+/// Spawns the job that delivers the outbox; `user_repo` fills it as users change.
+/// Delivery is at-least-once; receivers should dedupe on the `X-Webhook-Delivery` id.
+pub fn spawn_webhook_jobs(pool: MySqlPool) {
+    let poll_seconds = interval_from_env("WEBHOOK_POLL_SECONDS", DEFAULT_POLL_SECONDS);
+
+    tokio::spawn(async move {
+        let http = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to build webhook HTTP client");
+        let mut interval = tokio::time::interval(Duration::from_secs(poll_seconds));
+        loop {
+            interval.tick().await;
+            if let Err(e) = deliver_due(&pool, &http).await {
+                tracing::error!(error = %e, "Failed to deliver webhooks");
+            }
+        }
+    });
+}
+
+/// Runs a local receiver that prints every delivery, for trying out subscriptions.
+/// With a secret it also checks signatures; `status` is what it answers, so retries
+/// and the dead-letter state can be exercised with e.g. 500.
+pub async fn run_stub(addr: &str, secret: Option<String>, status: u16) -> std::io::Result<()> {
+    let status = actix_web::http::StatusCode::from_u16(status)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    println!("Webhook stub listening on http://{}", addr);
+
+    HttpServer::new(move || {
+        let secret = secret.clone();
+        App::new().default_service(web::to(move |req: HttpRequest, body: String| {
+            let secret = secret.clone();
+            async move {
+                let header = |name: &str| {
+                    req.headers()
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or("")
+                        .to_string()
+                };
+
+                let verdict = match &secret {
+                    Some(secret) => {
+                        let timestamp = header(TIMESTAMP_HEADER).parse::<i64>().unwrap_or_default();
+                        if sign(secret, timestamp, &body) == header(SIGNATURE_HEADER) {
+                            "signature ok"
+                        } else {
+                            "SIGNATURE MISMATCH"
+                        }
+                    }
+                    None => "signature not checked",
+                };
+
+                println!(
+                    "{} delivery {} ({}): {}",
+                    header(EVENT_HEADER),
+                    header(DELIVERY_HEADER),
+                    verdict,
+                    body
+                );
+                HttpResponse::build(status).finish()
+            }
+        }))
+    })
+    .bind(addr)?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_from_thirty_seconds() {
+        assert_eq!(backoff_secs(1), 30);
+        assert_eq!(backoff_secs(2), 60);
+        assert_eq!(backoff_secs(3), 120);
+        assert_eq!(backoff_secs(7), 30 * 64);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert_eq!(backoff_secs(11), MAX_BACKOFF_SECS);
+        assert_eq!(backoff_secs(i32::MAX), MAX_BACKOFF_SECS);
+        // A retried dead delivery starts again from zero attempts
+        assert_eq!(backoff_secs(0), 30);
+    }
+
+    #[test]
+    fn sign_matches_hmac_sha256() {
+        let body = r#"{"event":"user-created"}"#;
+        assert_eq!(
+            sign("secret", 1_700_000_000, body),
+            "sha256=316cb250f98a83db9c8dd0289d42d8dd6608334a28be15bf7b493654ec2bab83"
+        );
+    }
+
+    #[test]
+    fn sign_covers_timestamp_and_secret() {
+        let body = r#"{"event":"user-created"}"#;
+        let signature = sign("secret", 1_700_000_000, body);
+        assert_ne!(sign("secret", 1_700_000_001, body), signature);
+        assert_ne!(sign("other", 1_700_000_000, body), signature);
+    }
+}
@@ -1,5 +1,6 @@
 // src/router/index.js
 import { createRouter, createWebHistory } from 'vue-router';
+import { invoke } from '@tauri-apps/api/core';
 
 import LoginPage from '@views/Login.vue';
 import Register from '@views/Register.vue';
@@ -39,11 +40,15 @@ const router = createRouter({
 });
 
 // Navigation Guard
-router.beforeEach((to, from, next) => {
-  const token = localStorage.getItem('auth_token');
-  if (to.meta.requiresAuth && !token) {
+// The Rust side owns the session and restores a remembered one on first check
+router.beforeEach(async (to, from, next) => {
+  const session = to.meta.requiresAuth ? await invoke('current_session_tauri') : true;
+  if (!session) {
     next({ name: 'Login' });
   } else {
+    if (session !== true) {
+      localStorage.setItem('user_info', JSON.stringify({ name: session.name, role: session.role }));
+    }
     next();
   }
 });